@@ -14,16 +14,15 @@ use std::collections::HashMap as Map;
 use std::fs::File;
 use std::io::{Read, Write};
 use rand::SeedableRng;
-use engine::image_generator::ImageGenerator;
+use engine::image_generator::{light_images, ImageGenerator};
 use engine::image_generator::implementations::multithread::MultiThread;
 use engine::image_generator::implementations::one_thread::OneThreaded;
 use engine::image_generator::implementations::rayon::Library;
 use engine::renderer::Renderer;
-use engine::renderer::implementations::global_illumination::{
-    GlobalIllumination, PointLight, Solid, WithSky,
-};
+use engine::renderer::implementations::global_illumination::GlobalIllumination;
+use engine::renderer::objects::light::PointLight;
 
-use engine::renderer::implementations::sampling::{Black, Sampling};
+use engine::renderer::implementations::sampling::Sampling;
 use engine::renderer::implementations::simple_illumination::SimpleIllumination;
 use engine::renderer::objects::model::sphere::SphereModel;
 use engine::renderer::objects::model::{Model, Move, Rotate};
@@ -36,15 +35,28 @@ fn main() -> Result<(), eframe::Error> {
     file.read_to_string(&mut data).unwrap();
     let collection = GlobalIlluminationCollection::load(&data).unwrap();
 
+    let lights = collection.scene_lights();
     let renderer = GlobalIllumination::new(
         collection.scene,
-        collection.lights,
+        lights,
         4,
-        Solid::new([0.1; 3].into()),
-        //WithSky{}
+        collection.environment,
     );
-    // let renderer = SimpleIllumination::new(scene);
-    // let renderer = Sampling::new(scene, Black{}, 2, rand_xoshiro::Xoroshiro128PlusPlus::seed_from_u64(0), 5);
+    let renderer = match &collection.caustics {
+        Some(caustics) => renderer.with_caustics(caustics),
+        None => renderer,
+    };
+    let renderer = match &collection.light_sampling {
+        Some(sampling) => renderer.with_light_sampling(sampling.selection, sampling.picks),
+        None => renderer,
+    };
+    if let Some(pattern) = &collection.light_images {
+        for (index, image) in light_images(&collection.cameras[0], &renderer).iter().enumerate() {
+            image.save(pattern.replace("{}", &index.to_string())).unwrap();
+        }
+    }
+    // let renderer = SimpleIllumination::new(scene, collection.environment);
+    // let renderer = Sampling::new(scene, collection.environment, 2, rand_xoshiro::Xoroshiro128PlusPlus::seed_from_u64(0), 5);
 
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -213,7 +225,6 @@ fn glen_scene() -> Scene<TriangleModel>{
                 .roughness([0.0; 3].into())
                 .ior(1.3)
                 .transmittance([1.; 3].into())
-                .ambient([0.; 3].into())
                 .metallic([0.; 3].into())
                 .build()
                 .unwrap()
@@ -279,7 +290,6 @@ fn sphere_scene() -> Scene<SphereModel> {
                 .roughness([0.; 3].into())
                 .metallic([0.; 3].into())
                 .transmittance([1.; 3].into())
-                .ambient([0.; 3].into())
                 .ior(1.3)
                 .build()
                 .unwrap(),
@@ -291,7 +301,6 @@ fn sphere_scene() -> Scene<SphereModel> {
                 .color([0.5, 0.2, 0.2].into())
                 .roughness([0.8; 3].into())
                 .metallic([0.2; 3].into())
-                .ambient([0.3; 3].into())
                 .build()
                 .unwrap(),
         ),
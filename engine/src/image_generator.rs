@@ -1,11 +1,28 @@
 pub mod implementations;
 
 use image::RgbImage;
+use rayon::prelude::*;
 use crate::renderer::objects::camera::Camera;
-use crate::renderer::Renderer;
+use crate::renderer::objects::ray::{Rgb as RayRgb, RgbIntensity};
+use crate::renderer::{PerLight, Renderer};
 
 pub type Color = image::Rgb<u8>;
 
 pub trait ImageGenerator<C: Camera, R: Renderer> {
     fn create(&self, camera: &C, renderer: &R) -> RgbImage;
 }
+
+/// One image per light of `renderer`, lit by that light alone, all rendered in one pass.
+pub fn light_images<C: Camera + Sync, R: PerLight + Sync>(camera: &C, renderer: &R) -> Vec<RgbImage> {
+    let dims = camera.get_dimensions();
+    let rows: Vec<Vec<Vec<RgbIntensity>>> = (0..dims.height)
+        .into_par_iter()
+        .map(|y| (0..dims.width).map(|x| renderer.cast_lights(&camera.gen_ray(x, y))).collect())
+        .collect();
+
+    (0..renderer.light_count())
+        .map(|index| RgbImage::from_fn(dims.width as u32, dims.height as u32, |x, y| {
+            RayRgb(rows[y as usize][x as usize][index]).to_pixel().into()
+        }))
+        .collect()
+}
@@ -1,5 +1,5 @@
 use image::RgbImage;
-use crate::image_generator::{Color, ImageGenerator};
+use crate::image_generator::ImageGenerator;
 use crate::renderer::objects::camera::Camera;
 use crate::renderer::Renderer;
 use crate::renderer::objects::ray::Rgb as RayRgb;
@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 
-use image::{RgbImage, Rgb};
+use image::RgbImage;
 
 use rayon::iter::IntoParallelRefIterator;
 use rayon::prelude::*;
@@ -2,8 +2,66 @@ pub mod scene;
 pub mod objects;
 pub mod implementations;
 
+use std::ops::{Add, AddAssign};
+
 use objects::ray::{Ray, RgbIntensity};
 
 pub trait Renderer {
     fn cast(&self, ray: &Ray) -> RgbIntensity;
 }
+
+/// Renderer that can show what each of its lights contributes on its own.
+pub trait PerLight: Renderer {
+    fn light_count(&self) -> usize;
+
+    /// Light each of the lights brings along `ray` on its own, without emitters or
+    /// environment, all of it gathered by the same paths.
+    fn cast_lights(&self, ray: &Ray) -> Vec<RgbIntensity>;
+}
+
+/// Light along a ray, with the part each light brings split out in `lights` when tracing
+/// them apart; `lights` is empty otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LightLayers {
+    pub total: RgbIntensity,
+    pub lights: Vec<RgbIntensity>,
+}
+
+impl LightLayers {
+    /// Light none of `layers` lights brings, such as the environment.
+    pub fn unlit(total: RgbIntensity, layers: usize) -> Self {
+        LightLayers { total, lights: vec![RgbIntensity::zeros(); layers] }
+    }
+
+    /// Adds `light` brought by the light at `index`.
+    pub fn add_light(&mut self, index: usize, light: RgbIntensity) {
+        self.total += light;
+        if let Some(layer) = self.lights.get_mut(index) {
+            *layer += light;
+        }
+    }
+
+    pub fn map(&self, f: impl Fn(&RgbIntensity) -> RgbIntensity) -> Self {
+        LightLayers { total: f(&self.total), lights: self.lights.iter().map(&f).collect() }
+    }
+
+    pub fn component_mul(&self, factor: &RgbIntensity) -> Self {
+        self.map(|light| light.component_mul(factor))
+    }
+}
+
+impl AddAssign for LightLayers {
+    fn add_assign(&mut self, other: Self) {
+        self.total += other.total;
+        self.lights.iter_mut().zip(other.lights).for_each(|(layer, light)| *layer += light);
+    }
+}
+
+impl Add for LightLayers {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
+    }
+}
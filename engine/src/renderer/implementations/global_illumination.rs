@@ -1,175 +1,750 @@
 #![allow(dead_code)]
 
-use crate::renderer::Renderer;
+use crate::renderer::{LightLayers, PerLight, Renderer};
+use std::sync::Arc;
+
+use crate::renderer::objects::caustics::{Caustics, PhotonMap};
+use crate::renderer::objects::dielectric::DielectricStack;
+use crate::renderer::objects::environment::Environment;
 use crate::renderer::objects::hit::Hit;
+use crate::renderer::objects::light::{Light, ObjectSet};
+use crate::renderer::objects::light::sampler::{LightSampler, LightSelection};
 use crate::renderer::objects::material::RgbIntensity;
+use crate::renderer::objects::material::birefringence::Birefringence;
+use crate::renderer::objects::medium::Medium;
 use crate::renderer::objects::model::Model;
-use crate::renderer::objects::ray::{Ray, Rgb, Vector};
+use crate::renderer::objects::polarization::{self, LinearPolarizer, Mueller, Polarization, PolarizationOutput, StokesRgb};
+use crate::renderer::objects::ray::{Ray, Vector, RGB_WAVELENGTHS};
 use crate::renderer::scene::Scene;
 use nalgebra::Unit;
-use serde::{Deserialize, Serialize};
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct PointLight {
-    position: Vector,
-    color: RgbIntensity,
-    intensity: f32,
-}
-
-impl PointLight {
-    pub fn new(position: Vector, intensity: f32, color: RgbIntensity) -> Self {
-        PointLight {
-            position,
-            color,
-            intensity,
-        }
-    }
-}
-
-pub trait Ambient {
-    fn evaluate(&self, ray: &Ray, hit: &Option<Hit>) -> RgbIntensity;
-}
-
-#[derive(Clone, Debug)]
-pub struct WithSky {}
-impl Ambient for WithSky {
-    fn evaluate(&self, ray: &Ray, hit: &Option<Hit>) -> RgbIntensity {
-        let free = if hit.is_some() { -1.0 } else { 1.0 };
-        if ray.direction.z * free < 0.0 {
-            [0.1; 3].into()
-        } else {
-            RgbIntensity::new(0.7, 0.8, 1.0)
-        }
-    }
-}
-#[derive(Clone)]
-pub struct Solid {
-    color: RgbIntensity,
-}
-impl Solid {
-    pub fn new(color: RgbIntensity) -> Self {
-        Solid { color }
-    }
-}
-impl Ambient for Solid {
-    fn evaluate(&self, _ray: &Ray, _hit: &Option<Hit>) -> RgbIntensity {
-        self.color
-    }
-}
 
 #[derive(Clone, Debug)]
-pub struct GlobalIllumination<M: Model, A: Ambient> {
-    light_list: Vec<PointLight>,
+pub struct GlobalIllumination<M: Model> {
+    light_list: Vec<Light>,
     scene: Scene<M>,
     bounce_limit: usize,
-    ambient: A,
+    environment: Environment,
+    /// Indexes of emissive scene objects, lighting the scene like area lights.
+    emitters: Vec<usize>,
+    emitter_samples: usize,
+    /// Traces Stokes vectors instead of intensities when set.
+    polarization: Option<PolarizationOutput>,
+    /// Shades with a few of the lights instead of all of them when set.
+    light_sampler: Option<LightSampler>,
+    /// Shows the light of this light alone when set.
+    solo_light: Option<usize>,
+    /// Brings the light of point and spot lights through refractive objects when set.
+    photon_map: Option<Arc<PhotonMap>>,
 }
 
-impl<M: Model, A: Ambient> GlobalIllumination<M, A> {
+impl<M: Model> GlobalIllumination<M> {
     const EPSILON: f64 = 1e-6;
+    /// Part of the distance to a light within which a shadow ray counts as having reached it.
+    const REACH_TOLERANCE: f64 = 1e-6;
+    const EMITTER_SAMPLES: usize = 16;
+    const MARCH_STEPS: usize = 32;
 
     pub fn new(
         scene: Scene<M>,
-        light_list: Vec<PointLight>,
+        light_list: Vec<Light>,
         bounce_limit: usize,
-        ambient: A,
+        environment: Environment,
     ) -> Self {
+        let emitters = scene.objects.iter()
+            .enumerate()
+            .filter(|(_, object)| object.material().is_emissive() && object.area() > 0.)
+            .map(|(i, _)| i)
+            .collect();
+
         Self {
             scene,
             light_list,
             bounce_limit,
-            ambient,
+            environment,
+            emitters,
+            emitter_samples: Self::EMITTER_SAMPLES,
+            polarization: None,
+            light_sampler: None,
+            solo_light: None,
+            photon_map: None,
         }
     }
 
+    /// Shadow rays per emissive object and shading point, rounded up to a square grid.
+    pub fn with_emitter_samples(mut self, samples: usize) -> Self {
+        self.emitter_samples = samples;
+        self
+    }
+
+    /// Shades every point with `picks` lights chosen by `selection` plus the directional ones.
+    pub fn with_light_sampling(mut self, selection: LightSelection, picks: usize) -> Self {
+        self.light_sampler = Some(LightSampler::new(&self.light_list, selection, picks));
+        self
+    }
+
+    /// Shows only the light that the light at `index` of the light list brings, directly or
+    /// through reflections, without ambient light or glowing objects.
+    pub fn with_solo_light(mut self, index: usize) -> Self {
+        self.solo_light = Some(index);
+        self
+    }
+
+    /// Focuses the light of point and spot lights through refractive objects with a photon
+    /// map, shot right away, instead of letting it straight through them.
+    pub fn with_caustics(mut self, caustics: &Caustics) -> Self {
+        self.photon_map = Some(Arc::new(PhotonMap::new(&self.scene, &self.light_list, caustics)));
+        self
+    }
+
+    pub fn with_polarization(mut self, output: PolarizationOutput) -> Self {
+        self.polarization = Some(output);
+        self
+    }
+
+    /// Background seen by `ray` when it leaves the scene, the fill light at `hit` otherwise.
     fn _ambient(&self, ray: &Ray, hit: &Option<Hit>) -> RgbIntensity {
-        self.ambient.evaluate(ray, hit)
+        if self.solo_light.is_some() {
+            return RgbIntensity::zeros();
+        }
+        let Some(hit) = hit else {
+            return self.environment.background.evaluate(&ray.direction);
+        };
+        // Occlusion rays from a 2D Weyl sequence, shifted by a hash of the point.
+        let offset = Hit::hashed(&hit.pos, &ray.direction) as f64;
+        let mut k = 0.;
+        self.environment.fill(&self.scene, hit, || {
+            k += 1.;
+            ((offset + k * 0.754_877_666) % 1., (offset + k * 0.569_840_291) % 1.)
+        })
+    }
+
+    fn _emission(&self, hit: &Hit) -> RgbIntensity {
+        if self.solo_light.is_some() { RgbIntensity::zeros() } else { hit.emissivity() }
     }
 
-    fn _point_light_intensity(&self, light: &PointLight, hit: &Hit) -> RgbIntensity {
-        let dir_unnormed = light.position - hit.pos;
+    /// Fraction of light getting from `to` back to `from`; only transmissive objects, objects
+    /// outside of `shadow` and media let it through. Transmissive objects stop it too for
+    /// `caustic` light, which the photon map brings. `stack` holds the dielectrics `from` is
+    /// inside of. When tracing polarization, the light carries its Stokes vectors through the
+    /// surfaces on the way, so that crossed polarizers block it.
+    fn _shadow_transmittance(
+        &self,
+        from: &Vector,
+        to: &Vector,
+        stack: &DielectricStack,
+        shadow: &ObjectSet,
+        caustic: bool,
+    ) -> RgbIntensity {
+        let dir_unnormed = to - from;
         let distance = dir_unnormed.magnitude();
         let dir = Unit::new_normalize(dir_unnormed);
 
-        let mut light_ray = Ray::new(hit.pos, dir, 1.);
+        let mut stack = stack.clone();
+        let mut light_ray = Ray::new(*from, dir, stack.ior(from));
         let mut light_absorbed: RgbIntensity = [1.; 3].into();
+        let mut crossings = Vec::new();
 
-        while let Some(hit) = self.scene.intersect(&light_ray) {
-            if hit.material.transmission {
-                if hit.normal.dot(&dir) < 0. {
-                    light_absorbed = light_absorbed
-                        .component_mul(&hit.material.transmittance)
-                        .component_mul(&hit.material.color);
-                }
-            } else {
+        loop {
+            let ray_hit = self.scene.intersect_nested(&light_ray, &mut stack);
+            if let Some(medium) = self.scene.medium_along(&ray_hit) {
+                let remaining = distance - (light_ray.origin - from).magnitude();
+                let segment = ray_hit.as_ref().map_or(remaining, |hit| hit.factor.min(remaining));
+                light_absorbed.component_mul_assign(&medium.transmittance(&light_ray, segment));
+            }
+
+            let Some(hit) = ray_hit else {
+                break;
+            };
+            if (hit.pos - from).magnitude() >= distance * (1. - Self::REACH_TOLERANCE) - Self::EPSILON {
+                break;
+            }
+
+            let casts_shadow = shadow.contains(self.scene.objects[hit.object].name());
+            if casts_shadow && (caustic || !hit.material.transmission) {
                 light_absorbed = RgbIntensity::zeros();
                 break;
             }
 
+            let next = stack.crossed(&hit);
+            if casts_shadow && hit.front_face {
+                let (_, transmittance) = hit.specular_weights(&light_ray, next.ior(&hit.pos));
+                light_absorbed = light_absorbed.component_mul(&transmittance).component_mul(&hit.color());
+                if self.polarization.is_some() {
+                    crossings.push(Self::_crossing(&light_ray, &hit, next.ior(&hit.pos)));
+                } else {
+                    light_absorbed.component_mul_assign(&Self::_polarizer_factor(&hit));
+                }
+            }
+
             light_ray.origin = hit.pos + dir.scale(Self::EPSILON);
+            light_ray.ior = next.ior(&hit.pos);
+            stack = next;
+        }
+        if crossings.is_empty() {
+            return light_absorbed;
         }
-        light.color.component_mul(&light_absorbed) * light.intensity
-            / (distance as f32 + 1.).powf(2.)
+        light_absorbed.component_mul(&Self::_polarized_through(crossings, &dir))
     }
 
-    fn _light_exposure(&self, ray: &Ray, hit: &Hit) -> RgbIntensity {
-        self.light_list
-            .iter()
+    /// `s` axis of a shadow ray going through `hit` into a medium of index `ior`, and the
+    /// Mueller matrix of the surface in that frame.
+    fn _crossing(ray: &Ray, hit: &Hit, ior: f64) -> (Unit<Vector>, Mueller) {
+        let s_axis = polarization::s_axis(&hit.normal, &ray.direction).unwrap_or_else(|| ray.polarization_frame());
+        let mut mueller = Self::_transmission_mueller(ray, hit, ior);
+        if let Some(polarizer) = &hit.material.polarizer {
+            mueller = polarizer.mueller(&s_axis, &-ray.direction) * mueller;
+        }
+        (s_axis, mueller)
+    }
+
+    /// Share of unpolarized light going against `direction` that is left after the
+    /// `crossings` found going along it, applied to its Stokes vectors in reverse order.
+    fn _polarized_through(crossings: Vec<(Unit<Vector>, Mueller)>, direction: &Unit<Vector>) -> RgbIntensity {
+        let propagation = -*direction;
+        let mut light = Polarization::unpolarized(Hit::default_tangent(&propagation), &[1.; 3].into());
+        for (s_axis, mueller) in crossings.into_iter().rev() {
+            light = light.transformed(&mueller, s_axis, &propagation);
+        }
+        polarization::intensity(&light.stokes)
+    }
+
+    /// Light scattered once towards the ray origin over the first `distance` units of
+    /// the ray, marched in equal steps.
+    fn _in_scattering(&self, medium: &Medium, ray: &Ray, distance: f64, stack: &DielectricStack, layers: usize) -> LightLayers {
+        let mut scattered = LightLayers::unlit(RgbIntensity::zeros(), layers);
+        let Some((start, end)) = medium.range(ray, distance) else {
+            return scattered;
+        };
+        let phase = medium.phase();
+        let step = (end - start) / Self::MARCH_STEPS as f64;
+
+        let mut transmittance = RgbIntensity::from([1.; 3]);
+        for i in 0..Self::MARCH_STEPS {
+            let pos = ray.origin + ray.direction.scale(start + (i as f64 + 0.5) * step);
+            let density = medium.density(&pos) as f32;
+            let half_step = (medium.extinction() * density * (step / 2.) as f32).map(|depth| (-depth).exp());
+            transmittance.component_mul_assign(&half_step);
+
+            let factor = (medium.scattering * density).component_mul(&transmittance) * step as f32;
+            for (index, light_vector, intensity) in self._incident_lights(&pos, &ray.direction, stack, None) {
+                let phase = phase.evaluate(light_vector.dot(&ray.direction)) as f32;
+                scattered.add_light(index, factor.component_mul(&intensity) * phase);
+            }
+            transmittance.component_mul_assign(&half_step);
+        }
+        scattered
+    }
+
+    /// Transmittance of the medium between the ray origin and `hit`, and the light the medium
+    /// scatters back along the ray.
+    fn _through_medium(&self, ray: &Ray, hit: &Option<Hit>, stack: &DielectricStack, layers: usize) -> (RgbIntensity, LightLayers) {
+        match self.scene.medium_along(hit) {
+            Some(medium) => {
+                let distance = hit.as_ref().map_or(f64::INFINITY, |hit| hit.factor);
+                (medium.transmittance(ray, distance), self._in_scattering(medium, ray, distance, stack, layers))
+            }
+            None => ([1.; 3].into(), LightLayers::unlit(RgbIntensity::zeros(), layers)),
+        }
+    }
+
+    /// Irradiance from an emissive object, from a stratified grid of points spread by area. The
+    /// grid is shifted by hashes of the point and of the ray, so that neighbouring points see
+    /// different samples and soft shadows do not band.
+    fn _emitter_intensity(&self, emitter: &M, ray: &Ray, hit: &Hit, stack: &DielectricStack) -> RgbIntensity {
+        let side = (self.emitter_samples as f64).sqrt().ceil().max(1.) as usize;
+        let area_per_sample = emitter.area() / (side * side) as f64;
+        let (offset_u, offset_v) = (Hit::hashed(&hit.pos, &ray.direction) as f64, Hit::hashed(&hit.pos, &-ray.direction) as f64);
+
+        (0..side * side)
+            .filter_map(|i| {
+                let u = (((i % side) as f64 + offset_u) / side as f64).min(1. - f64::EPSILON);
+                let v = (((i / side) as f64 + offset_v) / side as f64).min(1. - f64::EPSILON);
+                emitter.sample(u, v)
+            })
             .map(|light| {
-                let light_vector = (light.position - hit.pos).normalize();
-
-                (hit.material.roughness * (hit.normal.dot(&light_vector).max(0.0) as f32)
-                    + hit.material.metallic
-                        * (ray
-                            .reflected_dir(&hit.normal)
-                            .dot(&light_vector)
-                            .max(0.0)
-                            .powf(hit.material.k) as f32))
-                    .component_mul(&self._point_light_intensity(light, hit))
+                let to_light = light.pos - hit.pos;
+                let distance_sq = to_light.magnitude_squared();
+                let light_vector = to_light / distance_sq.sqrt();
+
+                let cos_light = -light.normal.dot(&light_vector);
+                if cos_light <= 0. {
+                    return RgbIntensity::zeros();
+                }
+
+                (light.emissivity() * (cos_light * area_per_sample / distance_sq) as f32)
+                    .component_mul(&self._shadow_transmittance(&hit.pos, &light.pos, stack, &ObjectSet::default(), false))
+                    .component_mul(&self._shading(ray, hit, &light_vector))
             })
             .sum()
     }
 
-    fn _cast(&self, ray: &Ray, depth: usize, mut ior_stack: Vec<f64>) -> RgbIntensity {
-        let ray_hit = self.scene.intersect(ray);
-        let mut intensity = self._ambient(ray, &ray_hit);
+    /// Diffuse and Phong response to light coming from `light_vector`.
+    fn _shading(&self, ray: &Ray, hit: &Hit, light_vector: &Vector) -> RgbIntensity {
+        hit.roughness() * (hit.shading_normal().dot(light_vector).max(0.0) as f32)
+            + hit.metallic().component_mul(&Self::_specular_lobe(ray, hit, light_vector))
+    }
+
+    /// Phong lobe around the mirror direction, split with lobes around the diffraction orders
+    /// of a grating.
+    fn _specular_lobe(ray: &Ray, hit: &Hit, light_vector: &Vector) -> RgbIntensity {
+        let lobe = |direction: &Unit<Vector>| direction.dot(light_vector).max(0.0).powf(hit.material.k) as f32;
+        let mirror = lobe(&ray.reflected_dir(&hit.normal));
+        let Some(grating) = &hit.material.grating else {
+            return [mirror; 3].into();
+        };
+
+        let orders = grating.channel_orders(&ray.direction, &hit.normal, &hit.pos);
+        let mirror_share = grating.mirror_share(&orders);
+        RgbIntensity::from_fn(|channel, _| {
+            let diffracted = orders[channel].iter().map(lobe).sum::<f32>() / orders[channel].len().max(1) as f32;
+            mirror_share[channel] * mirror + grating.efficiency * diffracted
+        })
+    }
+
+    /// Light the grating of `hit` diffracts into the orders other than zero, each channel
+    /// along its own directions, and the part of the reflection left in the mirror direction.
+    /// Diffracted light is taken as unpolarized. The diffracted rays are shaded where they land
+    /// without bouncing further, which keeps facing gratings from branching without end.
+    fn _diffracted<'a>(
+        &'a self,
+        ray: &Ray,
+        hit: &Hit<'a>,
+        depth: usize,
+        stack: &DielectricStack<'a>,
+        layers: usize,
+    ) -> (LightLayers, RgbIntensity) {
+        let mut diffracted = LightLayers::unlit(RgbIntensity::zeros(), layers);
+        let Some(grating) = &hit.material.grating else {
+            return (diffracted, [1.; 3].into());
+        };
+
+        let orders = grating.channel_orders(&ray.direction, &hit.normal, &hit.pos);
+        for (channel, directions) in orders.iter().enumerate() {
+            let share = grating.efficiency / directions.len().max(1) as f32;
+            let only_channel = RgbIntensity::from_fn(|i, _| if i == channel { share } else { 0. });
+            for direction in directions {
+                let diffracted_ray = Ray::new(hit.pos + direction.scale(Self::EPSILON), *direction, ray.ior);
+                let last = (depth + 1).max(self.bounce_limit);
+                diffracted += self._cast_layers(&diffracted_ray, last, stack.clone(), layers).component_mul(&only_channel);
+            }
+        }
+        (diffracted, grating.mirror_share(&orders))
+    }
+
+    /// Shadowed light from the light at `index` reaching `pos`, from a stratified grid of shadow rays, as
+    /// directions towards the light with the intensity coming along each.
+    fn _incident_light(&self, index: usize, pos: &Vector, stack: &DielectricStack) -> Vec<(Vector, RgbIntensity)> {
+        let light = &self.light_list[index];
+        let caustic = self.photon_map.as_ref().is_some_and(|map| map.traces(index));
+        let side = (light.samples() as f64).sqrt().ceil().max(1.) as usize;
+        let share = 1. / (side * side) as f32;
+
+        (0..side * side)
+            .filter_map(|i| {
+                let u = ((i % side) as f64 + 0.5) / side as f64;
+                let v = ((i / side) as f64 + 0.5) / side as f64;
+                light.illuminate(u, v, pos)
+            })
+            .map(|(point, intensity)| {
+                (
+                    (point - pos).normalize(),
+                    intensity.component_mul(&self._shadow_transmittance(pos, &point, stack, &light.link().shadow, caustic)) * share,
+                )
+            })
+            .collect()
+    }
+
+    /// Lights to shade `pos` with and the factor on each: the solo light, those
+    /// `light_sampler` picks with stratified numbers offset by a hash of the point and of the
+    /// `direction` looked from, or all of them.
+    fn _chosen_lights(&self, pos: &Vector, direction: &Unit<Vector>) -> Vec<(usize, f32)> {
+        if let Some(solo) = self.solo_light {
+            return vec![(solo, 1.)];
+        }
+        let Some(sampler) = &self.light_sampler else {
+            return (0..self.light_list.len()).map(|i| (i, 1.)).collect();
+        };
+        let (offset, picks) = (Hit::hashed(pos, direction) as f64, sampler.picks());
+        let mut pick = 0;
+        let stratified = || {
+            pick += 1;
+            (pick as f64 - 1. + offset) / picks as f64
+        };
+        sampler.choose(pos, stratified)
+    }
+
+    /// `_incident_light` of the chosen lights that are linked to `object`, any of them in
+    /// media where there is no object, each with the index of its light.
+    fn _incident_lights(
+        &self,
+        pos: &Vector,
+        direction: &Unit<Vector>,
+        stack: &DielectricStack,
+        object: Option<usize>,
+    ) -> Vec<(usize, Vector, RgbIntensity)> {
+        let name = object.and_then(|i| self.scene.objects[i].name());
+        self._chosen_lights(pos, direction)
+            .into_iter()
+            .filter(|&(i, _)| object.is_none() || self.light_list[i].link().illuminate.contains(name))
+            .flat_map(|(i, weight)| {
+                self._incident_light(i, pos, stack)
+                    .into_iter()
+                    .map(move |(light_vector, intensity)| (i, light_vector, intensity * weight))
+            })
+            .collect()
+    }
+
+    fn _light_exposure(&self, ray: &Ray, hit: &Hit, stack: &DielectricStack, layers: usize) -> LightLayers {
+        let emitters: RgbIntensity = self.emitters
+            .iter()
+            .filter(|_| self.solo_light.is_none())
+            .map(|&i| self._emitter_intensity(&self.scene.objects[i], ray, hit, stack))
+            .sum();
+
+        let mut exposure = self._caustics(hit, layers);
+        exposure.total += emitters;
+        for (index, light_vector, intensity) in self._incident_lights(&hit.pos, &ray.direction, stack, Some(hit.object)) {
+            exposure.add_light(index, self._shading(ray, hit, &light_vector).component_mul(&intensity));
+        }
+        exposure
+    }
+
+    /// Diffuse response to the light the photon map brings to `hit` from the lights linked
+    /// to it.
+    fn _caustics(&self, hit: &Hit, layers: usize) -> LightLayers {
+        let mut caustics = LightLayers::unlit(RgbIntensity::zeros(), layers);
+        let Some(map) = &self.photon_map else {
+            return caustics;
+        };
+        let name = self.scene.objects[hit.object].name();
+        let lit_by = |i: usize| {
+            self.solo_light.is_none_or(|solo| solo == i) && self.light_list[i].link().illuminate.contains(name)
+        };
+        for (index, irradiance) in map.irradiance_by_light(hit, lit_by).into_iter().enumerate() {
+            caustics.add_light(index, hit.roughness().component_mul(&irradiance));
+        }
+        caustics
+    }
+
+    /// A polarizer lets through half of unpolarized light.
+    fn _polarizer_factor(hit: &Hit) -> RgbIntensity {
+        if hit.material.polarizer.is_some() { [0.5; 3].into() } else { [1.; 3].into() }
+    }
+
+    /// Index of refraction on the other side of the surface, `inside` being the dielectrics
+    /// there.
+    fn _next_ior(hit: &Hit, inside: &DielectricStack) -> f64 {
+        if hit.material.transmission { inside.ior(&hit.pos) } else { hit.material.ior }
+    }
+
+    /// Birefringent crystal the ray is refracted into, if any.
+    fn _crystal<'a>(hit: &Hit<'a>) -> Option<&'a Birefringence> {
+        hit.material.birefringence.as_ref().filter(|_| hit.front_face)
+    }
+
+    fn _cast<'a>(&'a self, ray: &Ray, depth: usize, stack: DielectricStack<'a>) -> RgbIntensity {
+        self._cast_layers(ray, depth, stack, 0).total
+    }
+
+    /// Same as `_cast`, with the light split between `layers` lights when not zero.
+    fn _cast_layers<'a>(&'a self, ray: &Ray, depth: usize, mut stack: DielectricStack<'a>, layers: usize) -> LightLayers {
+        let ray_hit = self.scene.intersect_nested(ray, &mut stack);
+        let (medium_transmittance, in_scattered) = self._through_medium(ray, &ray_hit, &stack, layers);
+        let mut light = LightLayers::unlit(self._ambient(ray, &ray_hit), layers);
 
         if let Some(hit) = ray_hit {
-            intensity += self._light_exposure(ray, &hit);
+            light += self._light_exposure(ray, &hit, &stack, layers);
 
             if depth < self.bounce_limit {
+                let inside = stack.crossed(&hit);
+                let ior = Self::_next_ior(&hit, &inside);
+                let (reflectance, transmittance) = hit.specular_weights(ray, ior);
+
+                let (diffracted, mirror_share) = self._diffracted(ray, &hit, depth, &stack, layers);
                 let reflected_dir = ray.reflected_dir(&hit.normal);
-                intensity += self
-                    ._cast(
-                        &Ray::new(hit.pos + reflected_dir.scale(Self::EPSILON), reflected_dir, ray.ior),
-                        depth + 1,
-                        ior_stack.clone(),
-                    )
-                    .component_mul(&hit.material.metallic);
+                let reflected_ray = Ray::new(hit.pos + reflected_dir.scale(Self::EPSILON), reflected_dir, ray.ior);
+                light += (self
+                    ._cast_layers(&self.scene.bend(reflected_ray, &hit), depth + 1, stack.clone(), layers)
+                    .component_mul(&mirror_share)
+                    + diffracted)
+                    .component_mul(&reflectance);
 
                 if hit.material.transmission {
-                    let ior = if hit.normal.dot(&ray.direction) <= 0. { // todo rethink
-                        ior_stack.push(hit.material.ior);
-                        hit.material.ior
-                    } else {
-                        ior_stack.pop().unwrap()
-                    };
-
-                    if let Some(refracted_dir) = ray.refracted_dir(&hit.normal, ior) {
-                        intensity += self
-                            ._cast(&Ray::new(hit.pos + refracted_dir.scale(Self::EPSILON), refracted_dir, ior), depth + 1, ior_stack)
-                            .component_mul(&hit.material.transmittance);
+                    for refraction in ray.refracted(&hit.normal, ior, Self::_crystal(&hit)) {
+                        let refracted_ray = self.scene.bend(
+                            refraction.ray(hit.pos + refraction.direction.scale(Self::EPSILON)),
+                            &hit,
+                        );
+                        let factor = transmittance.component_mul(&Self::_polarizer_factor(&hit)) * refraction.weight() as f32;
+                        light += self._cast_layers(&refracted_ray, depth + 1, inside.clone(), layers).component_mul(&factor);
+                    }
+                }
+            }
+            light = light.component_mul(&hit.color());
+            light.total += self._emission(&hit);
+        }
+        light.component_mul(&medium_transmittance) + in_scattered
+    }
+
+    /// Per channel Mueller matrix of the reflection, with `s` as the reference axis, reflecting
+    /// `reflectance` of unpolarized light head on. A coating gives its own reflectance at the
+    /// angle instead.
+    fn _reflection_mueller(ray: &Ray, hit: &Hit, ior: f64, reflectance: &RgbIntensity) -> [Mueller; 3] {
+        let cos_incident = ray.direction.dot(&hit.normal);
+        std::array::from_fn(|channel| {
+            let weight = reflectance[channel] as f64;
+            match &hit.material.coating {
+                Some(film) => {
+                    let [s, p] = film.amplitudes(cos_incident, ray.ior, ior, RGB_WAVELENGTHS[channel]);
+                    let mueller = polarization::from_amplitudes(s, p);
+                    polarization::reflection_weighted(mueller, mueller[(0, 0)], weight)
+                }
+                None => polarization::reflection_weighted(
+                    polarization::fresnel_reflection(cos_incident, ray.ior, ior),
+                    polarization::fresnel_reflection(1., ray.ior, ior)[(0, 0)],
+                    weight,
+                ),
+            }
+        })
+    }
+
+    /// Mueller matrix of the refraction, with `s` as the reference axis, relative to normal
+    /// incidence where the transmittance of the material holds; relative to the angle for
+    /// coatings, which weigh the angle in the transmittance.
+    fn _transmission_mueller(ray: &Ray, hit: &Hit, ior: f64) -> Mueller {
+        let mueller = polarization::fresnel_transmission(ray.direction.dot(&hit.normal), ray.ior, ior);
+        let head_on = match &hit.material.coating {
+            Some(_) => mueller[(0, 0)],
+            None => polarization::fresnel_transmission(1., ray.ior, ior)[(0, 0)],
+        };
+        polarization::transmission_weighted(mueller, head_on)
+    }
+
+    /// Same as `_cast`, but returns Stokes vectors in the frame of `ray.polarization_frame()`.
+    /// The Fresnel equations decide how much of the light is reflected and refracted depending
+    /// on the angle and on its polarization, the material weights holding at normal incidence.
+    fn _cast_polarized<'a>(&'a self, ray: &Ray, depth: usize, mut stack: DielectricStack<'a>) -> StokesRgb {
+        let ray_hit = self.scene.intersect_nested(ray, &mut stack);
+        let (medium_transmittance, in_scattered) = self._through_medium(ray, &ray_hit, &stack, 0);
+        let mut stokes = polarization::unpolarized(&self._ambient(ray, &ray_hit));
+
+        if let Some(hit) = ray_hit {
+            stokes += polarization::unpolarized(&self._light_exposure(ray, &hit, &stack, 0).total);
+
+            if depth < self.bounce_limit {
+                let inside = stack.crossed(&hit);
+                let ior = Self::_next_ior(&hit, &inside);
+                let (reflectance, transmittance) = hit.specular_weights(ray, ior);
+
+                let frame = ray.polarization_frame();
+                let s_axis = polarization::s_axis(&hit.normal, &ray.direction).unwrap_or(frame);
+                let from_s = polarization::rotation_between(&s_axis, &frame, &-ray.direction);
+                let to_s = |next: &Ray| polarization::rotation_between(&next.polarization_frame(), &s_axis, &-next.direction);
+
+                let reflected_dir = ray.reflected_dir(&hit.normal);
+                let reflected_ray = self.scene.bend(
+                    Ray::new(hit.pos + reflected_dir.scale(Self::EPSILON), reflected_dir, ray.ior),
+                    &hit,
+                );
+                let (diffracted, mirror_share) = self._diffracted(ray, &hit, depth, &stack, 0);
+                let diffracted = diffracted.total;
+                let reflected = to_s(&reflected_ray)
+                    * self._cast_polarized(&reflected_ray, depth + 1, stack.clone());
+                stokes += from_s
+                    * polarization::scaled(
+                        &polarization::per_channel(&Self::_reflection_mueller(ray, &hit, ior, &reflectance), &reflected),
+                        &mirror_share,
+                    );
+                stokes += polarization::unpolarized(&diffracted.component_mul(&reflectance));
+
+                let transmission = Self::_transmission_mueller(ray, &hit, ior);
+                let refractions = if hit.material.transmission {
+                    ray.refracted(&hit.normal, ior, Self::_crystal(&hit))
+                } else {
+                    vec![]
+                };
+                for refraction in refractions {
+                    let refracted_ray = self.scene.bend(
+                        refraction.ray(hit.pos + refraction.direction.scale(Self::EPSILON)),
+                        &hit,
+                    );
+                    let mut refracted = self._cast_polarized(&refracted_ray, depth + 1, inside.clone());
+                    if let Some(axis) = refraction.polarization {
+                        refracted = LinearPolarizer { axis }
+                            .mueller(&refracted_ray.polarization_frame(), &-refracted_ray.direction)
+                            * refracted;
                     }
+
+                    let mut transmitted = from_s * transmission * to_s(&refracted_ray) * refracted;
+                    if let Some(polarizer) = &hit.material.polarizer {
+                        transmitted = polarizer.mueller(&frame, &-ray.direction) * transmitted;
+                    }
+                    stokes += polarization::scaled(&transmitted, &transmittance);
                 }
             }
-            intensity = intensity.component_mul(&hit.material.color);
+            stokes = polarization::scaled(&stokes, &hit.color()) + polarization::unpolarized(&self._emission(&hit));
         }
-        intensity
+        polarization::scaled(&stokes, &medium_transmittance) + polarization::unpolarized(&in_scattered.total)
     }
 }
 
-impl<M: Model, A: Ambient> Renderer for GlobalIllumination<M, A> {
+impl<M: Model> Renderer for GlobalIllumination<M> {
     fn cast(&self, ray: &Ray) -> RgbIntensity {
-        self._cast(ray, 0, [1.0].into())
+        let Some(output) = &self.polarization else {
+            return self._cast(ray, 0, DielectricStack::new());
+        };
+
+        let stokes = self._cast_polarized(ray, 0, DielectricStack::new());
+        match output {
+            PolarizationOutput::Intensity => polarization::intensity(&stokes),
+            PolarizationOutput::DegreeOfPolarization => polarization::degree_of_polarization(&stokes),
+            PolarizationOutput::Filtered(filter) => {
+                polarization::intensity(&(filter.mueller(&ray.polarization_frame(), &-ray.direction) * stokes))
+            }
+        }
+    }
+}
+
+impl<M: Model> PerLight for GlobalIllumination<M> {
+    fn light_count(&self) -> usize {
+        self.light_list.len()
+    }
+
+    /// Intensities, also when tracing polarization.
+    fn cast_lights(&self, ray: &Ray) -> Vec<RgbIntensity> {
+        self._cast_layers(ray, 0, DielectricStack::new(), self.light_list.len()).lights
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::GlobalIllumination;
+    use crate::renderer::{PerLight, Renderer};
+    use crate::renderer::objects::environment::Environment;
+    use crate::renderer::objects::light::{Light, PointLight};
+    use crate::renderer::objects::material::MaterialBuilder;
+    use crate::renderer::objects::material::grating::{DiffractionGrating, Grooves};
+    use crate::renderer::objects::model::sphere::SphereModel;
+    use crate::renderer::objects::polarization::{LinearPolarizer, PolarizationOutput};
+    use crate::renderer::objects::ray::{Ray, Unit, Vector};
+    use crate::renderer::scene::Scene;
+
+    #[test]
+    fn test_crossed_polarizers_shadow() {
+        let sheet = |x: f64, y: f64| {
+            MaterialBuilder::default()
+                .color([1.; 3].into())
+                .transmission(true)
+                .transmittance([1.; 3].into())
+                .polarizer(Some(LinearPolarizer { axis: Vector::new(x, y, 0., 0.) }))
+                .build()
+                .unwrap()
+        };
+        let floor = MaterialBuilder::default().color([1.; 3].into()).roughness([1.; 3].into()).build().unwrap();
+        let lit_floor = |outer, polarized: bool| {
+            let scene = Scene::new(vec![
+                SphereModel::new(Vector::new(0., 0., 4., 0.), 0.5, sheet(1., 0.)),
+                SphereModel::new(Vector::new(0., 0., 4., 0.), 1., outer),
+                SphereModel::new(Vector::new(0., 0., -1000., 0.), 1000., floor.clone()),
+            ]);
+            let lights: Vec<Light> = vec![PointLight::new(Vector::new(0., 0., 4., 0.), 16., [1.; 3].into()).into()];
+            let mut renderer = GlobalIllumination::new(scene, lights, 0, Environment::default());
+            if polarized {
+                renderer = renderer.with_polarization(PolarizationOutput::Intensity);
+            }
+            renderer.cast(&Ray::new(Vector::new(0., -1., 1., 0.), Unit::new_normalize(Vector::new(0., 1., -1., 0.)), 1.)).x
+        };
+
+        // Half passes the first sheet, all of it the parallel one and none the crossed one.
+        assert_relative_eq!(lit_floor(sheet(1., 0.), true), 0.5, max_relative = 1e-6);
+        assert_relative_eq!(lit_floor(sheet(0., 1.), true), 0., epsilon = 1e-9);
+        assert_relative_eq!(lit_floor(sheet(0., 1.), false), 0.25, max_relative = 1e-6);
+    }
+
+    #[test]
+    fn test_brewster_reflection_through_filter() {
+        let ior = 1.5f64;
+        let floor = MaterialBuilder::default().color([1.; 3].into()).metallic([0.04; 3].into()).ior(ior).build().unwrap();
+        let scene = Scene::new(vec![SphereModel::new(Vector::new(0., 0., -1e5, 0.), 1e5, floor)]);
+        let brewster = ior.atan();
+        let ray = Ray::new(
+            Vector::new(0., -brewster.tan(), 1., 0.),
+            Unit::new_normalize(Vector::new(0., brewster.sin(), -brewster.cos(), 0.)),
+            1.,
+        );
+        let through = |axis: Vector| {
+            GlobalIllumination::new(scene.clone(), vec![], 1, Environment::constant([1.; 3].into()))
+                .with_polarization(PolarizationOutput::Filtered(LinearPolarizer { axis }))
+                .cast(&ray)
+                .x
+        };
+
+        let s_polarized = through(Vector::new(1., 0., 0., 0.));
+        assert!(s_polarized > 0.02);
+        assert!(through(Vector::new(0., brewster.cos(), brewster.sin(), 0.)) < 1e-4 * s_polarized);
+    }
+
+    #[test]
+    fn test_light_layers_add_up() {
+        let matte = MaterialBuilder::default().color([0.5; 3].into()).roughness([1.; 3].into()).build().unwrap();
+        let scene = Scene::new(vec![SphereModel::new(Vector::new(0., 0., -1000., 0.), 1000., matte)]);
+        let renderer = GlobalIllumination::new(scene, vec![
+            PointLight::new(Vector::new(0., 0., 4., 0.), 16., [1., 0., 0.].into()).into(),
+            PointLight::new(Vector::new(2., 0., 2., 0.), 8., [0., 1., 0.].into()).into(),
+        ], 2, Environment::default());
+        let ray = Ray::new(Vector::new(0., -1., 1., 0.), Unit::new_normalize(Vector::new(0., 1., -1., 0.)), 1.);
+
+        let parts = renderer.cast_lights(&ray);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].y, 0.);
+        assert_eq!(parts[1].x, 0.);
+        assert!(parts[0].x > 0. && parts[1].y > 0.);
+        assert_relative_eq!(parts[0] + parts[1], renderer.cast(&ray), max_relative = 1e-6);
+    }
+
+    #[test]
+    fn test_facing_gratings_stay_bounded() {
+        let grating = DiffractionGrating {
+            line_spacing: 1600.,
+            grooves: Grooves::Straight { direction: Vector::new(1., 0., 0., 0.) },
+            orders: 2,
+            efficiency: 0.5,
+        };
+        let mirror = MaterialBuilder::default().color([1.; 3].into()).metallic([0.8; 3].into()).roughness([1.; 3].into()).grating(Some(grating)).build().unwrap();
+        let scene = Scene::new(vec![
+            SphereModel::new(Vector::new(0., 0., -1000., 0.), 1000., mirror.clone()),
+            SphereModel::new(Vector::new(0., 0., 1002., 0.), 1000., mirror),
+        ]);
+        let lights: Vec<Light> = vec![PointLight::new(Vector::new(0., 0., 1., 0.), 4., [1.; 3].into()).into()];
+        let ray = Ray::new(Vector::new(0., 0., 1., 0.), Unit::new_normalize(Vector::new(0.3, 0.1, 1., 0.)), 1.);
+
+        // Every bounce hits a grating again; branching into twelve orders at each would not finish.
+        let light = GlobalIllumination::new(scene, lights, 10, Environment::default()).cast(&ray);
+        assert!(light.iter().all(|channel| channel.is_finite() && *channel > 0.));
+    }
+
+    #[test]
+    fn test_emitter_grid_shifts_between_points() {
+        let lamp = MaterialBuilder::default().color([0.; 3].into()).emissivity([10.; 3].into()).build().unwrap();
+        let floor = MaterialBuilder::default().color([1.; 3].into()).roughness([1.; 3].into()).build().unwrap();
+        let scene = Scene::new(vec![
+            SphereModel::new(Vector::new(0., 0., 4., 0.), 0.5, lamp),
+            SphereModel::new(Vector::new(0., 0., -1000., 0.), 1000., floor),
+        ]);
+        let renderer = |samples| GlobalIllumination::new(scene.clone(), vec![], 1, Environment::default()).with_emitter_samples(samples);
+        let ray = |i: usize| {
+            let x = (i % 20) as f64 * 1e-3;
+            let y = (i / 20) as f64 * 1e-3;
+            Ray::new(Vector::new(x, y - 1., 1., 0.), Unit::new_normalize(Vector::new(0., 1., -1., 0.)), 1.)
+        };
+
+        // One sample a point, averaged over nearby points, against many at one point.
+        let single = renderer(1);
+        let averaged = (0..400).map(|i| single.cast(&ray(i)).x).sum::<f32>() / 400.;
+        let reference = renderer(1024).cast(&ray(0)).x;
+        assert!(reference > 0.);
+        assert_relative_eq!(averaged, reference, max_relative = 0.1);
     }
 }
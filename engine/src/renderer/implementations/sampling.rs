@@ -2,49 +2,108 @@
 
 use std::cell::RefCell;
 use std::ops::DerefMut;
+use std::f64::consts::PI;
 use std::sync::{Arc, Mutex};
 use nalgebra::Vector4;
-use crate::renderer::Renderer;
+use crate::renderer::{LightLayers, PerLight, Renderer};
+use crate::renderer::objects::caustics::{Caustics, PhotonMap};
+use crate::renderer::objects::dielectric::DielectricStack;
+use crate::renderer::objects::environment::Environment;
 use crate::renderer::objects::hit::Hit;
+use crate::renderer::objects::light::{Light, ObjectSet};
+use crate::renderer::objects::light::sampler::{LightSampler, LightSelection};
 use crate::renderer::objects::material::RgbIntensity;
+use crate::renderer::objects::material::subsurface::Subsurface;
+use crate::renderer::objects::medium::Collision;
 use crate::renderer::objects::model::Model;
+use crate::renderer::objects::phase::HenyeyGreenstein;
 use crate::renderer::objects::ray::{Ray, Unit, Unit3, Vector3, Vector};
 use crate::renderer::scene::Scene;
 use rand::prelude::Rng;
 
-pub trait Environment {
-    fn evaluate(&self, ray: &Ray) -> RgbIntensity;
-}
-
-#[derive(Clone)]
-pub struct Black {}
-
-impl Environment for Black {
-    fn evaluate(&self, ray: &Ray) -> RgbIntensity {
-        [0.2; 3].into()
-    }
-}
-
 #[derive(Debug, Clone)]
-pub struct Sampling<M: Model, E: Environment, R: Rng> {
+pub struct Sampling<M: Model, R: Rng> {
     scene: Scene<M>,
-    environment: E,
+    environment: Environment,
     bounce_limit: usize,
     rng: Arc<Mutex<R>>,
-    samples: usize
+    samples: usize,
+    lights: Vec<Light>,
+    /// Indexes of emissive scene objects, sampled directly like the lights.
+    emitters: Vec<usize>,
+    /// Samples a few of the lights instead of all of them when set.
+    light_sampler: Option<LightSampler>,
+    /// Renders only the light with this index, without emitters and environment.
+    solo_light: Option<usize>,
+    /// Brings the light of point and spot lights through refractive objects when set.
+    photon_map: Option<Arc<PhotonMap>>,
+}
+
+/// Weight of a sample drawn with density `pdf` against another strategy with density `other`.
+fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    if pdf <= 0. { 0. } else { pdf * pdf / (pdf * pdf + other * other) }
 }
 
-impl<M: Model, E: Environment, R: Rng> Sampling<M, E, R> {
-    pub fn new(scene: Scene<M>, environment: E, bounce_limit: usize, rng: R, samples: usize) -> Self {
+/// Chances of following the reflected and the transmitted lobe, scaled down together when
+/// they add up to more than one so that each keeps its share.
+fn lobe_chances(reflectance: &RgbIntensity, transmittance: &RgbIntensity) -> (f32, f32) {
+    let (reflect, transmit) = (reflectance.mean(), transmittance.mean());
+    let total = (reflect + transmit).max(1.);
+    (reflect / total, transmit / total)
+}
+
+impl<M: Model, R: Rng> Sampling<M, R> {
+    const EPSILON: f64 = 1e-6;
+    const WALK_LIMIT: usize = 256;
+    const ENVIRONMENT_DISTANCE: f64 = 1e9;
+
+    pub fn new(scene: Scene<M>, environment: Environment, bounce_limit: usize, rng: R, samples: usize) -> Self {
+        let emitters = scene.objects.iter()
+            .enumerate()
+            .filter(|(_, object)| object.material().is_emissive() && object.area() > 0.)
+            .map(|(i, _)| i)
+            .collect();
+
         Self {
             scene,
             environment,
             bounce_limit,
             rng: Arc::new(Mutex::new(rng)),
-            samples
+            samples,
+            lights: Vec::new(),
+            emitters,
+            light_sampler: None,
+            solo_light: None,
+            photon_map: None,
         }
     }
 
+    /// Lights sampled directly at every diffuse bounce.
+    pub fn with_lights(mut self, lights: Vec<Light>) -> Self {
+        self.lights = lights;
+        self
+    }
+
+    /// Samples `picks` of the lights given to `with_lights` at each bounce, chosen by
+    /// `selection`, plus the directional ones.
+    pub fn with_light_sampling(mut self, selection: LightSelection, picks: usize) -> Self {
+        self.light_sampler = Some(LightSampler::new(&self.lights, selection, picks));
+        self
+    }
+
+    /// Focuses the light of the point and spot lights given to `with_lights` through
+    /// refractive objects with a photon map, instead of letting it straight through them.
+    pub fn with_caustics(mut self, caustics: &Caustics) -> Self {
+        self.photon_map = Some(Arc::new(PhotonMap::new(&self.scene, &self.lights, caustics)));
+        self
+    }
+
+    /// Renders the contribution of the light with `index` alone.
+    pub fn with_solo_light(mut self, index: usize) -> Self {
+        self.solo_light = Some(index);
+        self
+    }
+
     fn diffused_dir(&self, norm: &Unit) -> Unit {
         let norm3 = Vector3::from_homogeneous(norm.into_inner()).unwrap();
         let t1 = Unit3::new_normalize(
@@ -76,34 +135,422 @@ impl<M: Model, E: Environment, R: Rng> Sampling<M, E, R> {
         Unit::new_unchecked(original.into_inner() + norm.scale(norm.dot(original) * -2.))
     }
 
-    fn define_new_ray(&self, original: &Ray, hit: &Hit) -> Ray {
-        let specular = self.rng.lock().unwrap().random::<f32>();
+    /// Index on the other side of the surface, `inside` being the dielectrics there, and the
+    /// weights of the mirror and refracted rays.
+    fn specular(original: &Ray, hit: &Hit, inside: &DielectricStack) -> (f64, RgbIntensity, RgbIntensity) {
+        if !hit.material.transmission {
+            return (original.ior, hit.specular_weights(original, original.ior).0, RgbIntensity::zeros());
+        }
+        let ior = inside.ior(&hit.pos);
+        let (reflectance, transmittance) = hit.specular_weights(original, ior);
+        (ior, reflectance, transmittance)
+    }
 
-        Ray {
-            direction: if specular <= hit.material.metallic.x {
-                Self::reflected_ray(&original.direction, &hit.normal)
-            } else {
-                self.diffused_dir(&hit.normal)
-            },
-            origin: hit.pos,
-            ior: 1.
+    /// Chance of `define_new_ray` picking the diffuse bounce, which carries the direct light.
+    fn diffuse_chance(original: &Ray, hit: &Hit, stack: &DielectricStack) -> f32 {
+        if hit.material.subsurface.is_some() && hit.front_face {
+            return 0.;
+        }
+        let (_, reflectance, transmittance) = Self::specular(original, hit, &stack.crossed(hit));
+        let (reflect_chance, transmit_chance) = lobe_chances(&reflectance, &transmittance);
+        (1. - reflect_chance - transmit_chance).max(0.)
+    }
+
+    /// Closest hit along `ray` like `Scene::intersect_nested`, stochastic cutouts drawn anew
+    /// for every sample.
+    fn intersect<'a>(&'a self, ray: &Ray, stack: &mut DielectricStack<'a>) -> Option<Hit<'a>> {
+        self.scene.intersect_nested_with(ray, stack, |hit| hit.is_cut_out_with(|| self.rng.lock().unwrap().random()))
+    }
+
+    /// Fraction of light getting from `to` back to `from` through transmissive objects,
+    /// objects outside of `shadow` and media, `stack` holding the dielectrics `from` is inside of.
+    /// Transmissive objects stop `caustic` light, which the photon map brings instead.
+    fn transmittance_between(
+        &self,
+        from: &Vector,
+        to: &Vector,
+        stack: &DielectricStack,
+        shadow: &ObjectSet,
+        caustic: bool,
+    ) -> RgbIntensity {
+        let distance = (to - from).magnitude();
+        let mut stack = stack.clone();
+        let mut ray = Ray::new(*from, Unit::new_normalize(to - from), stack.ior(from));
+        let mut travelled = 0.;
+        let mut transmittance = RgbIntensity::from([1.; 3]);
+
+        loop {
+            let ray_hit = self.intersect(&ray, &mut stack);
+            let segment = ray_hit.as_ref().map_or(distance - travelled, |hit| hit.factor.min(distance - travelled));
+            if let Some(medium) = self.scene.medium_along(&ray_hit) {
+                let mut rng = self.rng.lock().unwrap();
+                transmittance.component_mul_assign(&medium.ratio_tracking(&ray, segment, rng.deref_mut()));
+            }
+
+            let Some(hit) = ray_hit else {
+                return transmittance;
+            };
+            travelled += hit.factor + Self::EPSILON;
+            if travelled >= distance {
+                return transmittance;
+            }
+            let casts_shadow = shadow.contains(self.scene.objects[hit.object].name());
+            if casts_shadow && (caustic || !hit.material.transmission) {
+                return RgbIntensity::zeros();
+            }
+
+            // Objects left out of the shadow are still crossed, so that the dielectrics the
+            // ray is inside of stay right for the ones after them.
+            let next = stack.crossed(&hit);
+            if casts_shadow && hit.front_face {
+                let (_, passed) = hit.specular_weights(&ray, next.ior(&hit.pos));
+                transmittance.component_mul_assign(&passed.component_mul(&hit.color()));
+            }
+            ray = Ray::new(hit.pos + ray.direction.scale(Self::EPSILON), ray.direction, next.ior(&hit.pos));
+            stack = next;
+        }
+    }
+
+    /// One point on each light or on each pick of the light sampler, with the unshadowed
+    /// intensity reaching `pos` from it. Only the lights linked to `object` when set, any of
+    /// them in media where there is no object.
+    fn light_samples(&self, pos: &Vector, object: Option<usize>) -> Vec<(usize, Vector, RgbIntensity)> {
+        let chosen: Vec<(usize, f32)> = match (self.solo_light, &self.light_sampler) {
+            (Some(solo), _) => vec![(solo, 1.)],
+            (None, Some(sampler)) => sampler.choose(pos, || self.rng.lock().unwrap().random()),
+            (None, None) => (0..self.lights.len()).map(|i| (i, 1.)).collect(),
+        };
+        let name = object.and_then(|i| self.scene.objects[i].name());
+        chosen
+            .into_iter()
+            .filter(|&(i, _)| object.is_none() || self.lights[i].link().illuminate.contains(name))
+            .filter_map(|(i, weight)| {
+                let mut rng = self.rng.lock().unwrap();
+                let (u, v) = (rng.random(), rng.random());
+                drop(rng);
+                self.lights[i].illuminate(u, v, pos).map(|(point, intensity)| (i, point, intensity * weight))
+            })
+            .collect()
+    }
+
+    /// Shadowed light reaching `pos` from the light at `index` at `point`.
+    fn shadowed(&self, index: usize, pos: &Vector, point: &Vector, intensity: RgbIntensity, stack: &DielectricStack) -> RgbIntensity {
+        let caustic = self.photon_map.as_ref().is_some_and(|map| map.traces(index));
+        intensity.component_mul(&self.transmittance_between(pos, point, stack, &self.lights[index].link().shadow, caustic))
+    }
+
+    /// Light from `lights` reflected diffusely at `hit` by next-event estimation, one sample
+    /// per light or per pick of the light sampler, and from the photon map, the material
+    /// color excluded.
+    fn direct_light(&self, hit: &Hit, stack: &DielectricStack, layers: usize) -> LightLayers {
+        let mut light = self.caustics(hit, layers);
+        for (i, point, intensity) in self.light_samples(&hit.pos, Some(hit.object)) {
+            let cos = hit.shading_normal().dot(&(point - hit.pos).normalize()).max(0.) as f32;
+            if cos > 0. {
+                light.add_light(i, self.shadowed(i, &hit.pos, &point, intensity, stack) * (cos / std::f32::consts::PI));
+            }
+        }
+        light
+    }
+
+    /// Light from `lights` scattered at `pos` in a medium towards where a ray going along
+    /// `direction` came from, by next-event estimation. Emitters and the environment are left
+    /// to the scattered ray, which finds them unweighted.
+    fn scattered_light(&self, pos: &Vector, direction: &Unit, phase: &HenyeyGreenstein, stack: &DielectricStack, layers: usize) -> LightLayers {
+        let mut light = LightLayers::unlit(RgbIntensity::zeros(), layers);
+        for (i, point, intensity) in self.light_samples(pos, None) {
+            let cos = direction.dot(&(point - pos).normalize());
+            light.add_light(i, self.shadowed(i, pos, &point, intensity, stack) * phase.evaluate(cos) as f32);
+        }
+        light
+    }
+
+    /// Light the photon map brings to `hit` from the lights linked to it, reflected diffusely.
+    fn caustics(&self, hit: &Hit, layers: usize) -> LightLayers {
+        let mut caustics = LightLayers::unlit(RgbIntensity::zeros(), layers);
+        let Some(map) = &self.photon_map else {
+            return caustics;
+        };
+        let name = self.scene.objects[hit.object].name();
+        let lit_by = |i: usize| self.solo_light.is_none_or(|solo| solo == i) && self.lights[i].link().illuminate.contains(name);
+        for (i, irradiance) in map.irradiance_by_light(hit, lit_by).into_iter().enumerate() {
+            caustics.add_light(i, irradiance / std::f32::consts::PI);
+        }
+        caustics
+    }
+
+    /// Environment light reflected diffusely at `hit` along one direction drawn by
+    /// `Background::sample`, weighted against finding it by a diffuse bounce picked with
+    /// `diffuse_chance`. The material color is excluded.
+    fn environment_light(&self, hit: &Hit, stack: &DielectricStack, diffuse_chance: f32) -> RgbIntensity {
+        let mut rng = self.rng.lock().unwrap();
+        let (u, v) = (rng.random(), rng.random());
+        drop(rng);
+        let Some((direction, pdf)) = self.environment.background.sample(u, v) else {
+            return RgbIntensity::zeros();
+        };
+
+        let cos = hit.shading_normal().dot(&direction);
+        if cos <= 0. || pdf <= 0. {
+            return RgbIntensity::zeros();
+        }
+        let weight = power_heuristic(pdf, diffuse_chance as f64 * cos / PI);
+        let far = hit.pos + direction.scale(Self::ENVIRONMENT_DISTANCE);
+        self.environment
+            .background
+            .evaluate(&direction)
+            .component_mul(&self.transmittance_between(&hit.pos, &far, stack, &ObjectSet::default(), false))
+            * (cos / (PI * pdf) * weight) as f32
+    }
+
+    /// Light of the emissive objects reflected diffusely at `hit`, from one point on each,
+    /// weighted against finding them by a diffuse bounce picked with `diffuse_chance`. The
+    /// material color is excluded.
+    fn emitter_light(&self, hit: &Hit, stack: &DielectricStack, diffuse_chance: f32) -> RgbIntensity {
+        self.emitters
+            .iter()
+            .filter_map(|&i| {
+                let emitter = &self.scene.objects[i];
+                let mut rng = self.rng.lock().unwrap();
+                let (u, v) = (rng.random(), rng.random());
+                drop(rng);
+                emitter.sample(u, v).map(|light| (emitter.area(), light))
+            })
+            .map(|(area, light)| {
+                let to_light = light.pos - hit.pos;
+                let distance_sq = to_light.magnitude_squared();
+                let light_vector = to_light / distance_sq.sqrt();
+                let (cos, cos_light) = (hit.shading_normal().dot(&light_vector), -light.normal.dot(&light_vector));
+                if cos <= 0. || cos_light <= 0. {
+                    return RgbIntensity::zeros();
+                }
+
+                let pdf = distance_sq / (cos_light * area);
+                let weight = power_heuristic(pdf, diffuse_chance as f64 * cos / PI);
+                light.emissivity().component_mul(&self.transmittance_between(&hit.pos, &light.pos, stack, &ObjectSet::default(), false))
+                    * (cos / (PI * pdf) * weight) as f32
+            })
+            .sum()
+    }
+
+    /// Density over solid angle of `emitter_light` picking the point of `hit` from `origin`.
+    fn emitter_pdf(&self, origin: &Vector, hit: &Hit) -> f64 {
+        if !self.emitters.contains(&hit.object) {
+            return 0.;
+        }
+        let to_light = hit.pos - origin;
+        let cos_light = -hit.normal.dot(&to_light.normalize());
+        if !hit.front_face || cos_light <= 0. {
+            return 0.;
+        }
+        to_light.magnitude_squared() / (cos_light * self.scene.objects[hit.object].area())
+    }
+
+    /// Picks the next bounce and returns it with the throughput factor of the chosen lobe,
+    /// the material color excluded, the dielectrics the new ray is inside of and whether the
+    /// bounce was diffuse.
+    fn define_new_ray<'a>(
+        &self,
+        original: &Ray,
+        hit: &Hit<'a>,
+        stack: &DielectricStack<'a>,
+    ) -> (Ray, RgbIntensity, DielectricStack<'a>, bool) {
+        let choice = self.rng.lock().unwrap().random::<f32>();
+
+        let entering = hit.front_face;
+        let inside = stack.crossed(hit);
+        let (ior, reflectance, transmittance) = Self::specular(original, hit, &inside);
+        let (reflect_chance, transmit_chance) = lobe_chances(&reflectance, &transmittance);
+
+        let reflected = |weight: RgbIntensity| {
+            let direction = Self::reflected_ray(&original.direction, &hit.normal);
+            (Ray::new(hit.pos + direction.scale(Self::EPSILON), direction, original.ior), weight, stack.clone(), false)
+        };
+
+        if let Some(subsurface) = &hit.material.subsurface
+            && entering
+        {
+            if choice < reflect_chance {
+                return reflected(reflectance / reflect_chance);
+            }
+            return match original.refracted_dir(&hit.normal, hit.material.ior) {
+                Some(direction) => {
+                    let inside = Ray::new(hit.pos + direction.scale(Self::EPSILON), direction, hit.material.ior);
+                    let (ray, weight) = self.random_walk(inside, subsurface, hit.object, stack.ior(&hit.pos))
+                        .unwrap_or((original.clone(), RgbIntensity::zeros()));
+                    (ray, weight, stack.clone(), false)
+                }
+                None => reflected(RgbIntensity::from([1.; 3])),
+            };
+        }
+
+        if choice < reflect_chance {
+            match self.diffracted(original, hit) {
+                Some((ray, weight)) => (ray, reflectance.component_mul(&weight) / reflect_chance, stack.clone(), false),
+                None => reflected(reflectance / reflect_chance),
+            }
+        } else if choice < reflect_chance + transmit_chance {
+            match original.refracted_dir(&hit.normal, ior) {
+                Some(direction) => (
+                    Ray::new(hit.pos + direction.scale(Self::EPSILON), direction, ior),
+                    transmittance / transmit_chance,
+                    inside,
+                    false,
+                ),
+                None => reflected(RgbIntensity::from([1.; 3])),
+            }
+        } else {
+            (
+                Ray::new(hit.pos, self.diffused_dir(&hit.shading_normal()), original.ior),
+                RgbIntensity::from([1.; 3]),
+                stack.clone(),
+                true,
+            )
         }
     }
+    /// Picks between the mirror reflection and the diffraction orders of the grating of `hit`,
+    /// returning the ray with its throughput; `None` without a grating. Each order carries a
+    /// single channel.
+    fn diffracted(&self, original: &Ray, hit: &Hit) -> Option<(Ray, RgbIntensity)> {
+        let grating = hit.material.grating.as_ref()?;
+        let orders = grating.channel_orders(&original.direction, &hit.normal, &hit.pos);
+
+        let mut rng = self.rng.lock().unwrap();
+        let (choice, channel) = (rng.random::<f32>(), rng.random_range(0..3));
+        if choice >= grating.efficiency {
+            let direction = Self::reflected_ray(&original.direction, &hit.normal);
+            return Some((
+                Ray::new(hit.pos + direction.scale(Self::EPSILON), direction, original.ior),
+                grating.mirror_share(&orders) / (1. - grating.efficiency),
+            ));
+        }
+        let direction = match orders[channel].len() {
+            0 => return Some((original.clone(), RgbIntensity::zeros())),
+            count => orders[channel][rng.random_range(0..count)],
+        };
+
+        let weight = RgbIntensity::from_fn(|i, _| if i == channel { 3. } else { 0. });
+        Some((Ray::new(hit.pos + direction.scale(Self::EPSILON), direction, original.ior), weight))
+    }
+
+    /// Scatters a ray that entered the subsurface material of the object at `object` until it
+    /// leaves through the boundary of that object, other objects inside being ignored. Leaves
+    /// into a medium of index `outside`. Returns `None` when the walk escapes an open mesh
+    /// or runs out of steps. Lights are not sampled inside, as the way out to them bends at the
+    /// boundary; the light comes in through where the walk leaves instead.
+    fn random_walk(&self, mut ray: Ray, subsurface: &Subsurface, object: usize, outside: f64) -> Option<(Ray, RgbIntensity)> {
+        let phase = HenyeyGreenstein::new(subsurface.anisotropy);
+        let mut weight = RgbIntensity::from([1.; 3]);
+
+        for _ in 0..Self::WALK_LIMIT {
+            let boundary = self.scene.intersect_object(&ray, object)?;
+
+            let mut rng = self.rng.lock().unwrap();
+            let (distance_u, u1, u2) = (rng.random::<f64>(), rng.random::<f64>(), rng.random::<f64>());
+            drop(rng);
+
+            let distance = -(1. - distance_u).ln() * subsurface.mean_free_path;
+            if distance < boundary.factor {
+                weight.component_mul_assign(&subsurface.albedo);
+                let direction = phase.sample(&ray.direction, u1, u2);
+                ray = Ray::new(ray.origin + ray.direction.scale(distance), direction, ray.ior);
+                continue;
+            }
+
+            ray = match ray.refracted_dir(&boundary.normal, outside) {
+                Some(direction) => {
+                    return Some((Ray::new(boundary.pos + direction.scale(Self::EPSILON), direction, outside), weight));
+                }
+                None => {
+                    let direction = ray.reflected_dir(&boundary.normal);
+                    Ray::new(boundary.pos + direction.scale(Self::EPSILON), direction, ray.ior)
+                }
+            };
+        }
+        None
+    }
+
     fn cast_once(&self, ray: &Ray) -> RgbIntensity {
+        self.cast_once_layers(ray, 0).total
+    }
+
+    /// Same as `cast_once`, with the light of `lights` also split by light when `layers` is
+    /// not zero.
+    fn cast_once_layers(&self, ray: &Ray, layers: usize) -> LightLayers {
         let mut current_ray = ray.clone();
+        let mut stack = DielectricStack::new();
 
         let mut color = RgbIntensity::from([1.; 3]);
-        let mut emission_collected = RgbIntensity::from([0.; 3]);
+        let mut emission_collected = LightLayers::unlit(RgbIntensity::zeros(), layers);
+        // Density of the last bounce when it was diffuse, for weighting the light it finds
+        // against the direct sampling of that light.
+        let mut diffuse_pdf: Option<f64> = None;
 
         for _ in 0..self.bounce_limit {
-            if let Some(hit) = self.scene.intersect(&current_ray) {
-                current_ray = self.define_new_ray(&current_ray, &hit);
+            let ray_hit = self.intersect(&current_ray, &mut stack);
+
+            if let Some(medium) = self.scene.medium_along(&ray_hit) {
+                let distance = ray_hit.as_ref().map_or(f64::INFINITY, |hit| hit.factor);
+                let mut rng = self.rng.lock().unwrap();
+                match medium.delta_tracking(&current_ray, distance, rng.deref_mut()) {
+                    Collision::Scattered(pos, weight) => {
+                        let phase = medium.phase();
+                        let direction = phase.sample(&current_ray.direction, rng.random(), rng.random());
+                        drop(rng);
+                        color.component_mul_assign(&weight);
+                        if !self.lights.is_empty() {
+                            emission_collected += self.scattered_light(&pos, &current_ray.direction, &phase, &stack, layers).component_mul(&color);
+                        }
+                        current_ray = Ray::new(pos, direction, current_ray.ior);
+                        diffuse_pdf = None;
+                        continue;
+                    }
+                    Collision::Passed(weight) => color.component_mul_assign(&weight),
+                    Collision::Absorbed => break,
+                }
+            }
 
-                let emitted = &hit.material.emissivity;
-                emission_collected += emitted.component_mul(&color);
-                color = hit.material.color.component_mul(&color);
+            if let Some(hit) = ray_hit {
+                let diffuse_chance = Self::diffuse_chance(&current_ray, &hit, &stack);
+                if diffuse_chance > 0. && !self.lights.is_empty() {
+                    emission_collected += self.direct_light(&hit, &stack, layers)
+                        .component_mul(&(hit.color().component_mul(&color) * diffuse_chance));
+                }
+                // A solo light leaves out emitters and the environment.
+                let solo = self.solo_light.is_some();
+                if diffuse_chance > 0. && !solo {
+                    let fill = self.environment.fill(&self.scene, &hit, || {
+                        let mut rng = self.rng.lock().unwrap();
+                        (rng.random(), rng.random())
+                    });
+                    let sampled = self.environment_light(&hit, &stack, diffuse_chance)
+                        + self.emitter_light(&hit, &stack, diffuse_chance)
+                        + fill;
+                    emission_collected.total += sampled.component_mul(&hit.color()).component_mul(&color) * diffuse_chance;
+                }
+
+                let emitted = match diffuse_pdf {
+                    Some(pdf) => hit.emissivity() * power_heuristic(pdf, self.emitter_pdf(&current_ray.origin, &hit)) as f32,
+                    None => hit.emissivity(),
+                };
+                if !solo {
+                    emission_collected.total += emitted.component_mul(&color);
+                }
+
+                let (next_ray, weight, next_stack, diffuse) = self.define_new_ray(&current_ray, &hit, &stack);
+                diffuse_pdf = diffuse.then(|| diffuse_chance as f64 * hit.shading_normal().dot(&next_ray.direction).max(0.) / PI);
+                stack = next_stack;
+                current_ray = self.scene.bend(next_ray, &hit);
+
+                color = hit.color().component_mul(&weight).component_mul(&color);
+                if color.max() <= 0. {
+                    break;
+                }
             } else {
-                emission_collected = self.environment.evaluate(&current_ray).component_mul(&color) + emission_collected;
+                if self.solo_light.is_none() {
+                    let weight = diffuse_pdf.map_or(1., |pdf| power_heuristic(pdf, self.environment.background.pdf(&current_ray.direction)));
+                    emission_collected.total += self.environment.background.evaluate(&current_ray.direction).component_mul(&color) * weight as f32;
+                }
                 break;
             }
         }
@@ -112,14 +559,268 @@ impl<M: Model, E: Environment, R: Rng> Sampling<M, E, R> {
     }
 }
 
-impl<M: Model, E: Environment, R: Rng> Renderer for Sampling<M, E, R> {
+impl<M: Model, R: Rng> Renderer for Sampling<M, R> {
     fn cast(&self, ray: &Ray) -> RgbIntensity {
         (1. / self.samples as f32) * (0..self.samples).map(|_| self.cast_once(ray)).sum::<RgbIntensity>()
     }
 }
 
+impl<M: Model, R: Rng> PerLight for Sampling<M, R> {
+    fn light_count(&self) -> usize {
+        self.lights.len()
+    }
+
+    fn cast_lights(&self, ray: &Ray) -> Vec<RgbIntensity> {
+        let mut lights = vec![RgbIntensity::zeros(); self.lights.len()];
+        for _ in 0..self.samples {
+            let sample = self.cast_once_layers(ray, self.lights.len());
+            lights.iter_mut().zip(sample.lights).for_each(|(light, sampled)| *light += sampled);
+        }
+        lights.iter().map(|light| light / self.samples as f32).collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use approx::assert_relative_eq;
+    use rand::SeedableRng;
+
+    use super::{lobe_chances, Sampling};
+    use crate::renderer::implementations::global_illumination::GlobalIllumination;
+    use crate::renderer::{PerLight, Renderer};
+    use crate::renderer::objects::environment::Environment;
+    use crate::renderer::objects::dielectric::DielectricStack;
+    use crate::renderer::objects::light::{Light, ObjectSet, PointLight};
+    use crate::renderer::objects::material::{Cutout, Material, MaterialBuilder, RgbIntensity};
+    use crate::renderer::objects::material::subsurface::Subsurface;
+    use crate::renderer::objects::medium::Medium;
+    use crate::renderer::objects::model::sphere::SphereModel;
+    use crate::renderer::objects::ray::{Ray, Unit, Vector};
+    use crate::renderer::scene::Scene;
+
+    #[test]
+    fn test_small_lamp_converges() {
+        let (radius, height, radiance) = (0.2, 4., 10.);
+        let lamp = MaterialBuilder::default()
+            .color([0.; 3].into())
+            .emissivity([radiance as f32; 3].into())
+            .build()
+            .unwrap();
+        let floor = MaterialBuilder::default().color([0.5; 3].into()).roughness([1.; 3].into()).build().unwrap();
+        let scene = Scene::new(vec![
+            SphereModel::new(Vector::new(0., 0., height, 0.), radius, lamp),
+            SphereModel::new(Vector::new(0., 0., -1000., 0.), 1000., floor),
+        ]);
+        let renderer = Sampling::new(scene, Environment::default(), 3, rand_pcg::Pcg64Mcg::seed_from_u64(0), 1);
+
+        let ray = Ray::new(Vector::new(0., -1., 1., 0.), Unit::new_normalize(Vector::new(0., 1., -1., 0.)), 1.);
+        let n = 4000;
+        let casts: Vec<f64> = (0..n).map(|_| renderer.cast_once(&ray).x as f64).collect();
+        let mean = casts.iter().sum::<f64>() / n as f64;
+        let deviation = (casts.iter().map(|c| (c - mean).powi(2)).sum::<f64>() / n as f64).sqrt();
+
+        // A diffuse surface of albedo 0.5 under a sphere of solid angle π (r/d)².
+        let expected = 0.5 * radiance * (radius / height).powi(2);
+        assert_relative_eq!(mean, expected, max_relative = 0.05);
+        // Bounces alone find the lamp once in 400 tries, deviating some 20 times the mean.
+        assert!(deviation < 2. * mean);
+    }
+
+    #[test]
+    fn test_light_linking() {
+        let matte = MaterialBuilder::default().color([0.5; 3].into()).roughness([1.; 3].into()).build().unwrap();
+        let scene = Scene::new(vec![
+            SphereModel::new(Vector::new(0., 0., 2., 0.), 0.5, matte.clone()).with_name("blocker"),
+            SphereModel::new(Vector::new(0., 0., -1000., 0.), 1000., matte).with_name("floor"),
+        ]);
+        let lamp = |link: &str| {
+            let mut light = PointLight::new(Vector::new(0., 0., 4., 0.), 16., [1.; 3].into());
+            light.link = serde_yaml::from_str(link).unwrap();
+            Light::from(light)
+        };
+        let floor_under = |light: Light| {
+            let renderer = Sampling::new(scene.clone(), Environment::default(), 1, rand_pcg::Pcg64Mcg::seed_from_u64(0), 1)
+                .with_lights(vec![light]);
+            renderer.cast(&Ray::new(Vector::new(0., -1., 1., 0.), Unit::new_normalize(Vector::new(0., 1., -1., 0.)), 1.)).x
+        };
+
+        assert_eq!(floor_under(lamp("{}")), 0.);
+        assert_relative_eq!(floor_under(lamp("{shadow: {exclude: [blocker]}}")), 0.5 / std::f32::consts::PI, max_relative = 1e-4);
+        assert_eq!(floor_under(lamp("{illuminate: {include: [blocker]}, shadow: {exclude: [blocker]}}")), 0.);
+    }
 
+    #[test]
+    fn test_light_contributions_add_up() {
+        let matte = MaterialBuilder::default().color([0.5; 3].into()).roughness([1.; 3].into()).build().unwrap();
+        let scene = Scene::new(vec![SphereModel::new(Vector::new(0., 0., -1000., 0.), 1000., matte)]);
+        let renderer = Sampling::new(scene, Environment::default(), 1, rand_pcg::Pcg64Mcg::seed_from_u64(0), 1).with_lights(vec![
+            PointLight::new(Vector::new(0., 0., 4., 0.), 16., [1., 0., 0.].into()).into(),
+            PointLight::new(Vector::new(2., 0., 2., 0.), 8., [0., 1., 0.].into()).into(),
+        ]);
+        let ray = Ray::new(Vector::new(0., -1., 1., 0.), Unit::new_normalize(Vector::new(0., 1., -1., 0.)), 1.);
+
+        assert_eq!(renderer.light_count(), 2);
+        let parts = renderer.cast_lights(&ray);
+        assert_eq!(parts[0].y, 0.);
+        assert_eq!(parts[1].x, 0.);
+        assert_relative_eq!(parts[0] + parts[1], renderer.cast(&ray), max_relative = 1e-6);
+    }
+
+    #[test]
+    fn test_lights_sampled_in_fog() {
+        let black = MaterialBuilder::default().color([0.; 3].into()).build().unwrap();
+        let scene = Scene::new(vec![SphereModel::new(Vector::new(0., 0., -1000., 0.), 1000., black)])
+            .with_medium(Medium::homogeneous(RgbIntensity::zeros(), [0.1; 3].into(), 0.3));
+        let lights: Vec<Light> = vec![PointLight::new(Vector::new(0., 2., 1., 0.), 10., [1.; 3].into()).into()];
+        let ray = Ray::new(Vector::new(0., -2., 1., 0.), Unit::new_normalize(Vector::new(0., 1., -0.2, 0.)), 1.);
+
+        // Single scattering alone, against the marched estimate.
+        let marched = GlobalIllumination::new(scene.clone(), lights.clone(), 1, Environment::default()).cast(&ray);
+        assert!(marched.x > 0.01);
+        let renderer = Sampling::new(scene, Environment::default(), 1, rand_pcg::Pcg64Mcg::seed_from_u64(0), 20_000).with_lights(lights);
+        assert_relative_eq!(renderer.cast(&ray), marched, max_relative = 0.05);
+    }
+
+    #[test]
+    fn test_stochastic_cutout_averages_to_opacity() {
+        let leaf = MaterialBuilder::default().color([0.; 3].into()).metallic([0.; 3].into()).opacity(0.3).build().unwrap();
+        let scene = Scene::new(vec![SphereModel::new(Vector::zeros(), 1., leaf)]);
+        let renderer = Sampling::new(scene, Environment::constant([1.; 3].into()), 1, rand_pcg::Pcg64Mcg::seed_from_u64(0), 4000);
+        let ray = Ray::new(Vector::new(0., 0., -3., 0.), Unit::new_normalize(Vector::new(0., 0., 1., 0.)), 1.);
+
+        // Through both sides of the sphere.
+        assert_relative_eq!(renderer.cast(&ray).x, 0.7 * 0.7, epsilon = 0.03);
+    }
 
+    #[test]
+    fn test_shadow_through_cutout() {
+        let floor = MaterialBuilder::default().color([0.5; 3].into()).roughness([1.; 3].into()).build().unwrap();
+        let leaf_material = |cutout: Cutout| MaterialBuilder::default().color([0.; 3].into()).opacity(0.3).cutout(cutout).build().unwrap();
+        let lights: Vec<Light> = vec![PointLight::new(Vector::new(0., 0., 4., 0.), 16., [1.; 3].into()).into()];
+        let scene = |leaf: Option<Cutout>| {
+            let mut objects = vec![SphereModel::new(Vector::new(0., 0., -1000., 0.), 1000., floor.clone())];
+            objects.extend(leaf.map(|cutout| SphereModel::new(Vector::new(0., 0., 2., 0.), 0.5, leaf_material(cutout))));
+            Scene::new(objects)
+        };
+        let ray = Ray::new(Vector::new(0., -1., 1., 0.), Unit::new_normalize(Vector::new(0., 1., -1., 0.)), 1.);
+        let sampled = |leaf: Option<Cutout>| {
+            Sampling::new(scene(leaf), Environment::default(), 1, rand_pcg::Pcg64Mcg::seed_from_u64(0), 4000)
+                .with_lights(lights.clone())
+                .cast(&ray)
+                .x
+        };
+
+        let lit = sampled(None);
+        assert!(lit > 0.);
+        assert_relative_eq!(sampled(Some(Cutout::Stochastic)), 0.7 * 0.7 * lit, max_relative = 0.05);
+        let traced = |leaf: Option<Cutout>| GlobalIllumination::new(scene(leaf), lights.clone(), 1, Environment::default()).cast(&ray).x;
+        assert_relative_eq!(traced(Some(Cutout::Threshold(0.5))), traced(None), max_relative = 1e-6);
+    }
+
+    #[test]
+    fn test_lobe_chances_keep_their_share() {
+        assert_eq!(lobe_chances(&[0.2; 3].into(), &[0.5; 3].into()), (0.2, 0.5));
+        let (reflect, transmit) = lobe_chances(&[0.6; 3].into(), &[0.9; 3].into());
+        assert_relative_eq!(reflect + transmit, 1.);
+        assert_relative_eq!(transmit / reflect, 1.5);
+    }
+
+    #[test]
+    fn test_random_walk_keeps_white_energy() {
+        let subsurface = Subsurface { albedo: [1.; 3].into(), mean_free_path: 0.25, anisotropy: 0. };
+        let wax = MaterialBuilder::default().ior(1.3).subsurface(Some(subsurface.clone())).build().unwrap();
+        // The core inside is not part of the boundary the walk leaves through.
+        let scene = Scene::new(vec![
+            SphereModel::new(Vector::zeros(), 1., wax),
+            SphereModel::new(Vector::zeros(), 0.3, Material::default()),
+        ]);
+        let renderer = Sampling::new(scene, Environment::default(), 1, rand_pcg::Pcg64Mcg::seed_from_u64(0), 1);
+
+        let n = 2000;
+        let mut total = 0.;
+        for _ in 0..n {
+            let ray = Ray::new(Vector::new(0., 0., 0.9, 0.), Unit::new_normalize(Vector::new(0., 0., -1., 0.)), 1.3);
+            if let Some((out, weight)) = renderer.random_walk(ray, &subsurface, 0, 1.) {
+                assert_relative_eq!(out.origin.magnitude(), 1., epsilon = 1e-4);
+                assert!(out.direction.dot(&out.origin) > 0.);
+                total += weight.x;
+            }
+        }
+        assert!(total / n as f32 > 0.98);
+    }
+
+    #[test]
+    fn test_shadow_crosses_unshadowing_glass() {
+        let dielectric = |color: f32, priority: u32| {
+            MaterialBuilder::default()
+                .color([color; 3].into())
+                .transmittance([1.; 3].into())
+                .transmission(true)
+                .ior(1.5)
+                .priority(priority)
+                .build()
+                .unwrap()
+        };
+        // The shell outranks the tinted core, whose surfaces are then false interfaces.
+        let scene = Scene::new(vec![
+            SphereModel::new(Vector::zeros(), 2., dielectric(1., 2)).with_name("shell"),
+            SphereModel::new(Vector::zeros(), 1., dielectric(0.5, 1)).with_name("core"),
+        ]);
+        let renderer = Sampling::new(scene, Environment::default(), 1, rand_pcg::Pcg64Mcg::seed_from_u64(0), 1);
+        let shadow = ObjectSet { include: None, exclude: vec!["shell".into()] };
+
+        let (from, to) = (Vector::new(0., 0., -5., 0.), Vector::new(0., 0., 5., 0.));
+        let passed = renderer.transmittance_between(&from, &to, &DielectricStack::new(), &shadow, false);
+        assert_relative_eq!(passed, RgbIntensity::from([1.; 3]), max_relative = 1e-6);
+    }
+
+    #[test]
+    fn test_water_in_glass_through_both_renderers() {
+        let dielectric = |color: f32, ior: f64, priority: u32| {
+            MaterialBuilder::default()
+                .color([color; 3].into())
+                .transmittance([1.; 3].into())
+                .transmission(true)
+                .ior(ior)
+                .priority(priority)
+                .build()
+                .unwrap()
+        };
+        // The water's front face lies inside the glass and is a false interface.
+        let pair = || {
+            vec![
+                SphereModel::new(Vector::zeros(), 1., dielectric(1., 1.5, 2)),
+                SphereModel::new(Vector::new(0., 0., 1.5, 0.), 1., dielectric(0.5, 1.33, 1)),
+            ]
+        };
+        let ray = Ray::new(Vector::new(0., 0., -3., 0.), Unit::new_normalize(Vector::new(0., 0., 1., 0.)), 1.);
+        let sampled = Sampling::new(Scene::new(pair()), Environment::constant([1.; 3].into()), 5, rand_pcg::Pcg64Mcg::seed_from_u64(0), 4)
+            .cast(&ray);
+        assert_relative_eq!(sampled, RgbIntensity::from([0.5; 3]), max_relative = 1e-4);
+        let traced = GlobalIllumination::new(Scene::new(pair()), vec![], 5, Environment::constant([1.; 3].into())).cast(&ray);
+        assert_relative_eq!(traced, RgbIntensity::from([0.5; 3]), max_relative = 1e-4);
+
+        // A floor lit straight through the pair is not darkened by the water it never enters.
+        let floor = MaterialBuilder::default().color([0.5; 3].into()).roughness([1.; 3].into()).build().unwrap();
+        let lights: Vec<Light> = vec![PointLight::new(Vector::new(0., 0., 4., 0.), 16., [1.; 3].into()).into()];
+        let scene = |with_pair: bool| {
+            let mut objects = vec![SphereModel::new(Vector::new(0., 0., -1004., 0.), 1000., floor.clone())];
+            if with_pair {
+                objects.extend(pair());
+            }
+            Scene::new(objects)
+        };
+        let to_floor = Ray::new(Vector::new(0., -1., -3., 0.), Unit::new_normalize(Vector::new(0., 1., -1., 0.)), 1.);
+        let sampled = |with_pair: bool| {
+            Sampling::new(scene(with_pair), Environment::default(), 1, rand_pcg::Pcg64Mcg::seed_from_u64(0), 1)
+                .with_lights(lights.clone())
+                .cast(&to_floor)
+                .x
+        };
+        assert!(sampled(false) > 0.);
+        assert_relative_eq!(sampled(true), sampled(false), max_relative = 1e-4);
+        let traced = |with_pair: bool| GlobalIllumination::new(scene(with_pair), lights.clone(), 1, Environment::default()).cast(&to_floor).x;
+        assert!(traced(false) > 0.);
+        assert_relative_eq!(traced(true), traced(false), max_relative = 1e-4);
+    }
 }
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
-use crate::renderer::objects::material::{Material, RgbIntensity};
+use crate::renderer::objects::environment::Environment;
+use crate::renderer::objects::material::RgbIntensity;
 use crate::renderer::objects::model::Model;
 use crate::renderer::objects::ray::{Ray, Vector};
 use crate::renderer::{Renderer};
@@ -10,16 +11,16 @@ use crate::renderer::scene::Scene;
 #[derive(Clone, Debug)]
 pub struct SimpleIllumination<M: Model> {
     scene: Scene<M>,
-    background: Material,
+    environment: Environment,
     light: Vector,
     light_color: RgbIntensity
 }
 
 impl<M: Model> SimpleIllumination<M> {
-    pub fn new(scene: Scene<M>) -> SimpleIllumination<M> {
+    pub fn new(scene: Scene<M>, environment: Environment) -> SimpleIllumination<M> {
         SimpleIllumination {
             scene,
-            background: Material::default(),
+            environment,
             light: Vector::new(10., -10., 10., 0.),
             light_color: RgbIntensity::new(1., 1., 1.)
         }
@@ -29,18 +30,20 @@ impl<M: Model> SimpleIllumination<M> {
 impl<M: Model> Renderer for SimpleIllumination<M> {
     fn cast(&self, ray: &Ray) -> RgbIntensity {
         match self.scene.intersect(ray) {
-            None => { self.background.color },
+            None => self.environment.background.evaluate(&ray.direction),
             Some(hit) => {
-                let cos_reflection = (self.light - hit.pos).normalize().dot(&hit.normal).max(0.).powf(hit.material.k) as f32;
+                let normal = hit.shading_normal();
+                let cos_reflection = (self.light - hit.pos).normalize().dot(&normal).max(0.).powf(hit.material.k) as f32;
 
-                let cos_diffusive = ray.direction.dot(&-hit.normal).max(0.) as f32;
+                let cos_diffusive = ray.direction.dot(&-normal).max(0.) as f32;
 
                 let mut color_res = RgbIntensity::zeros();
+                let (color, metallic, roughness) = (hit.color(), hit.metallic(), hit.roughness());
 
                 for i in 0..3 {
 
-                    let reflection_intensity = self.light_color[i] * hit.material.metallic[i] * cos_reflection;
-                    let diffusion_intensity = hit.material.color[i] * hit.material.roughness[i] * cos_diffusive;
+                    let reflection_intensity = self.light_color[i] * metallic[i] * cos_reflection;
+                    let diffusion_intensity = color[i] * roughness[i] * cos_diffusive;
                     color_res[i] = diffusion_intensity + reflection_intensity;
                 };
 
@@ -48,4 +51,22 @@ impl<M: Model> Renderer for SimpleIllumination<M> {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::SimpleIllumination;
+    use crate::renderer::Renderer;
+    use crate::renderer::objects::environment::Environment;
+    use crate::renderer::objects::model::sphere::SphereModel;
+    use crate::renderer::objects::ray::{Ray, RgbIntensity, Unit, Vector};
+    use crate::renderer::scene::Scene;
+
+    #[test]
+    fn test_miss_shows_environment() {
+        let sky: RgbIntensity = [0.2, 0.4, 0.8].into();
+        let renderer = SimpleIllumination::new(Scene::<SphereModel>::new(vec![]), Environment::constant(sky));
+        let ray = Ray::new(Vector::zeros(), Unit::new_normalize(Vector::new(0., 1., 0., 0.)), 1.);
+
+        assert_eq!(renderer.cast(&ray), sky);
+    }
+}
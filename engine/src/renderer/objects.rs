@@ -1,6 +1,16 @@
 pub mod ray;
 pub mod camera;
+pub mod caustics;
 pub mod model;
 pub mod hit;
+pub mod light;
+pub mod dielectric;
 pub mod material;
+pub mod medium;
+pub mod environment;
+pub mod environment_map;
+pub mod sky;
+pub mod texture;
+pub mod phase;
+pub mod polarization;
 
@@ -4,7 +4,7 @@ pub mod fisheye;
 pub mod perspective;
 
 use serde::{Deserialize, Serialize};
-use crate::renderer::objects::ray::{Ray, Vector};
+use crate::renderer::objects::ray::Ray;
 
 pub trait Camera {
     fn gen_ray(&self, u: usize, v: usize) -> Ray;
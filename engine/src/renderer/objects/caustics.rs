@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::f64::consts::{PI, TAU};
+
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::dielectric::DielectricStack;
+use crate::renderer::objects::hit::Hit;
+use crate::renderer::objects::light::Light;
+use crate::renderer::objects::model::Model;
+use crate::renderer::objects::ray::{Ray, RgbIntensity, Unit, Vector};
+use crate::renderer::scene::Scene;
+
+/// Light of the point and spot lights reaching surfaces through refractive objects, carried
+/// by photons refracted on the way instead of by straight shadow rays.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Caustics {
+    /// Photons shot from each light, most of them missing the refractive objects.
+    #[serde(default = "Caustics::default_photons")]
+    pub photons: usize,
+    /// Photons within this distance of a shading point count towards its light; larger
+    /// blurs the caustics, smaller makes them noisy.
+    #[serde(default = "Caustics::default_radius")]
+    pub radius: f64,
+    /// Surfaces a photon goes through or reflects off before it is dropped.
+    #[serde(default = "Caustics::default_bounces")]
+    pub bounces: usize,
+}
+
+impl Caustics {
+    fn default_photons() -> usize {
+        200_000
+    }
+
+    fn default_radius() -> f64 {
+        0.05
+    }
+
+    fn default_bounces() -> usize {
+        16
+    }
+}
+
+impl Default for Caustics {
+    fn default() -> Self {
+        Caustics { photons: Self::default_photons(), radius: Self::default_radius(), bounces: Self::default_bounces() }
+    }
+}
+
+#[derive(Debug)]
+struct Photon {
+    pos: Vector,
+    /// Towards where the photon came from.
+    incoming: Vector,
+    power: RgbIntensity,
+    light: usize,
+}
+
+/// Photons left on the first opaque surface they reached after going through a refractive
+/// object, bucketed by cells of the gathering radius.
+#[derive(Debug)]
+pub struct PhotonMap {
+    radius: f64,
+    photons: Vec<Photon>,
+    cells: HashMap<[i64; 3], Vec<usize>>,
+    /// Lights the map carries the light of through refractive objects.
+    traced: Vec<bool>,
+}
+
+impl PhotonMap {
+    const EPSILON: f64 = 1e-6;
+
+    pub fn new<M: Model>(scene: &Scene<M>, lights: &[Light], caustics: &Caustics) -> Self {
+        let mut map = PhotonMap { radius: caustics.radius, photons: Vec::new(), cells: HashMap::new(), traced: vec![false; lights.len()] };
+        for (index, light) in lights.iter().enumerate() {
+            let origin = match light {
+                Light::Point(light) => light.position,
+                Light::Spot(spot) => spot.light.position,
+                _ => continue,
+            };
+            map.traced[index] = true;
+            map.shoot(scene, index, light, &origin, caustics);
+        }
+
+        for (i, photon) in map.photons.iter().enumerate() {
+            map.cells.entry(Self::cell(&photon.pos, map.radius)).or_default().push(i);
+        }
+        map
+    }
+
+    /// Whether the light at `index` reaches through refractive objects only by photons.
+    pub fn traces(&self, index: usize) -> bool {
+        self.traced.get(index).copied().unwrap_or(false)
+    }
+
+    /// Caustic light falling on `hit` from the lights `lit_by` accepts.
+    pub fn irradiance(&self, hit: &Hit, lit_by: impl Fn(usize) -> bool) -> RgbIntensity {
+        self.irradiance_by_light(hit, lit_by).iter().sum()
+    }
+
+    /// `irradiance` split by the light it comes from, one entry per light.
+    pub fn irradiance_by_light(&self, hit: &Hit, lit_by: impl Fn(usize) -> bool) -> Vec<RgbIntensity> {
+        let [x, y, z] = Self::cell(&hit.pos, self.radius);
+        let mut gathered = vec![RgbIntensity::zeros(); self.traced.len()];
+        for cell in (-1..=1).flat_map(|i| (-1..=1).flat_map(move |j| (-1..=1).map(move |k| [x + i, y + j, z + k]))) {
+            for &i in self.cells.get(&cell).into_iter().flatten() {
+                let photon = &self.photons[i];
+                let offset = photon.pos - hit.pos;
+                let normal = hit.shading_normal();
+                // A flat disk around the point, so photons on nearby parallel surfaces stay out.
+                let near = offset.magnitude_squared() < self.radius * self.radius
+                    && offset.dot(&normal).abs() < 0.25 * self.radius;
+                if near && photon.incoming.dot(&normal) > 0. && lit_by(photon.light) {
+                    gathered[photon.light] += photon.power;
+                }
+            }
+        }
+        let area = (PI * self.radius * self.radius) as f32;
+        gathered.iter().map(|light| light / area).collect()
+    }
+
+    fn cell(pos: &Vector, size: f64) -> [i64; 3] {
+        [pos.x, pos.y, pos.z].map(|c| (c / size).floor() as i64)
+    }
+
+    /// Photons of `light` spread evenly over all directions, each with its share of the flux,
+    /// from a generator seeded by the light index so renders repeat.
+    fn shoot<M: Model>(&mut self, scene: &Scene<M>, index: usize, light: &Light, origin: &Vector, caustics: &Caustics) {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(index as u64);
+        let count = caustics.photons.max(1);
+        let side = (count as f64).sqrt().ceil() as usize;
+
+        for i in 0..side * side {
+            let z = 1. - 2. * ((i / side) as f64 + rng.random::<f64>()) / side as f64;
+            let phi = TAU * ((i % side) as f64 + rng.random::<f64>()) / side as f64;
+            let r = (1. - z * z).max(0.).sqrt();
+            let target = origin + Vector::new(r * phi.cos(), r * phi.sin(), z, 0.);
+
+            // The light seen from a unit away is its intensity in that direction.
+            let Some((start, intensity)) = light.illuminate(rng.random(), rng.random(), &target) else {
+                continue;
+            };
+            let power = intensity * (4. * PI / (side * side) as f64) as f32;
+            if power.max() > 0. {
+                self.trace(scene, index, Ray::new(start, Unit::new_normalize(target - start), 1.), power, caustics.bounces, &mut rng);
+            }
+        }
+    }
+
+    /// Follows a photon through refractive objects, picking reflection or refraction by their
+    /// weights, and keeps it where it lands on an opaque surface after at least one of them.
+    fn trace<M: Model>(&mut self, scene: &Scene<M>, light: usize, mut ray: Ray, mut power: RgbIntensity, bounces: usize, rng: &mut impl Rng) {
+        let mut stack = DielectricStack::new();
+        ray.ior = stack.ior(&ray.origin);
+        let mut refracted = false;
+
+        for _ in 0..bounces {
+            let Some(hit) = scene.intersect_nested(&ray, &mut stack) else {
+                return;
+            };
+            if !hit.material.transmission {
+                if refracted {
+                    self.photons.push(Photon { pos: hit.pos, incoming: -ray.direction.into_inner(), power, light });
+                }
+                return;
+            }
+            refracted = true;
+
+            let inside = stack.crossed(&hit);
+            let ior = inside.ior(&hit.pos);
+            let (reflectance, transmittance) = hit.specular_weights(&ray, ior);
+            let refractions = ray.refracted(&hit.normal, ior, hit.material.birefringence.as_ref().filter(|_| hit.front_face));
+            let tint = if hit.front_face { hit.color() } else { [1.; 3].into() };
+
+            let reflect = reflectance.mean() as f64;
+            let refract = if refractions.is_empty() { 0. } else { transmittance.mean() as f64 };
+            if reflect + refract <= 0. && !refractions.is_empty() {
+                return;
+            }
+            if rng.random::<f64>() * (reflect + refract) < refract {
+                let refraction = &refractions[rng.random_range(0..refractions.len())];
+                let chance = refract / (reflect + refract) / refractions.len() as f64;
+                power = power.component_mul(&transmittance).component_mul(&tint) * (refraction.weight() / chance) as f32;
+                if hit.material.polarizer.is_some() {
+                    power *= 0.5;
+                }
+                ray = scene.bend(refraction.ray(hit.pos + refraction.direction.scale(Self::EPSILON)), &hit);
+                stack = inside;
+                continue;
+            }
+
+            // Totally reflected when there is no refraction.
+            if refract > 0. {
+                power = power.component_mul(&reflectance) * ((reflect + refract) / reflect) as f32;
+            }
+            let reflected = ray.reflected_dir(&hit.normal);
+            ray = scene.bend(Ray::new(hit.pos + reflected.scale(Self::EPSILON), reflected, ray.ior), &hit);
+            if power.max() <= 0. {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use approx::assert_relative_eq;
+
+    use super::{Caustics, PhotonMap};
+    use crate::renderer::objects::light::{Light, PointLight};
+    use crate::renderer::objects::material::MaterialBuilder;
+    use crate::renderer::objects::model::sphere::SphereModel;
+    use crate::renderer::objects::ray::{Ray, Unit, Vector};
+    use crate::renderer::scene::Scene;
+
+    #[test]
+    fn test_ball_lens_focuses_light() {
+        let glass = MaterialBuilder::default()
+            .color([1.; 3].into())
+            .metallic([0.04; 3].into())
+            .ior(1.5)
+            .transmission(true)
+            .transmittance([0.96; 3].into())
+            .build()
+            .unwrap();
+        let floor = MaterialBuilder::default().color([0.8; 3].into()).roughness([1.; 3].into()).build().unwrap();
+        let scene = Scene::new(vec![
+            SphereModel::new(Vector::new(0., 0., 3., 0.), 1., glass),
+            SphereModel::new(Vector::new(0., 0., -1000., 0.), 1000., floor),
+        ]);
+        let lights: Vec<Light> = vec![PointLight::new(Vector::new(0., 0., 10., 0.), 100., [1.; 3].into()).into()];
+        let map = PhotonMap::new(&scene, &lights, &Caustics { photons: 400_000, radius: 0.1, bounces: 8 });
+        assert!(map.traces(0));
+
+        let floor_at = |x: f64| {
+            let ray = Ray::new(Vector::new(x, 0.01, 1., 0.), Unit::new_normalize(Vector::new(0., 0., -1., 0.)), 1.);
+            map.irradiance(&scene.intersect(&ray).unwrap(), |_| true).x
+        };
+        // Straight down, the light alone would give 100 / 10².
+        assert!(floor_at(0.) > 4.);
+        assert!(floor_at(0.) > floor_at(0.5) && floor_at(0.5) > floor_at(1.2));
+        assert_eq!(floor_at(1.6), 0.);
+
+        // All the light entering the lens ends up on the floor but for what its surfaces take.
+        let entering = 100. * TAU * (1. - (48f64 / 49.).sqrt());
+        let landed: f64 = map.photons.iter().map(|photon| photon.power.x as f64).sum();
+        assert_relative_eq!(landed, entering * 0.96 * 0.96, max_relative = 0.05);
+    }
+}
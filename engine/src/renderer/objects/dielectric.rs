@@ -0,0 +1,106 @@
+use crate::renderer::objects::hit::Hit;
+use crate::renderer::objects::material::Material;
+use crate::renderer::objects::ray::Vector;
+
+/// Transmissive objects a ray is inside of. Where objects overlap, as with water filling a
+/// glass, the one with the highest `priority` is the medium and the surfaces of the others
+/// are false interfaces the ray passes straight through.
+#[derive(Debug, Clone, Default)]
+pub struct DielectricStack<'a> {
+    inside: Vec<&'a Material>,
+}
+
+impl<'a> DielectricStack<'a> {
+    pub fn new() -> Self {
+        DielectricStack { inside: Vec::new() }
+    }
+
+    /// Material of the medium; of equal priorities the one entered last wins.
+    fn top(&self) -> Option<&'a Material> {
+        self.inside.iter().copied().max_by_key(|material| material.priority)
+    }
+
+    /// Index of refraction of the medium at `pos`, vacuum outside of everything.
+    pub fn ior(&self, pos: &Vector) -> f64 {
+        self.top().map_or(1., |material| material.ior_at(pos))
+    }
+
+    /// Whether the surface of `hit` separates two different media.
+    pub fn is_boundary(&self, hit: &Hit<'a>) -> bool {
+        !hit.material.transmission
+            || self.top().is_none_or(|top| {
+                std::ptr::eq(top, hit.material) || top.priority <= hit.material.priority
+            })
+    }
+
+    /// Stack on the other side of the surface of `hit`.
+    pub fn crossed(&self, hit: &Hit<'a>) -> Self {
+        let mut next = self.clone();
+        if !hit.material.transmission {
+            return next;
+        }
+        if hit.front_face {
+            next.inside.push(hit.material);
+        } else if let Some(i) = next.inside.iter().rposition(|material| std::ptr::eq(*material, hit.material)) {
+            next.inside.remove(i);
+        }
+        next
+    }
+
+    /// Stack after going through a hole cut out of the surface of `hit`: leaving the object
+    /// that way still counts, entering it does not.
+    pub fn cut_through(&self, hit: &Hit<'a>) -> Self {
+        if hit.front_face { self.clone() } else { self.crossed(hit) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::DielectricStack;
+    use crate::renderer::objects::hit::Hit;
+    use crate::renderer::objects::material::Material;
+    use crate::renderer::objects::ray::{Unit, Uv, Vector};
+
+    fn hit(material: &Material, front_face: bool) -> Hit<'_> {
+        let normal = Unit::new_normalize(Vector::new(0., 0., 1., 0.));
+        let direction = if front_face { -normal } else { normal };
+        Hit::new(1., Vector::zeros(), material, normal, Uv::zeros(), Hit::default_tangent(&normal)).facing(&direction)
+    }
+
+    #[test]
+    fn test_water_in_glass() {
+        let dielectric = |ior, priority| Material { ior, priority, transmission: true, ..Material::default() };
+        let glass = dielectric(1.5, 2);
+        let water = dielectric(1.33, 1);
+        let outside = DielectricStack::new();
+
+        let in_glass = outside.crossed(&hit(&glass, true));
+        assert_relative_eq!(in_glass.ior(&Vector::zeros()), 1.5);
+
+        assert!(!in_glass.is_boundary(&hit(&water, true)));
+        let in_both = in_glass.crossed(&hit(&water, true));
+        assert_relative_eq!(in_both.ior(&Vector::zeros()), 1.5);
+
+        assert!(in_both.is_boundary(&hit(&glass, false)));
+        let in_water = in_both.crossed(&hit(&glass, false));
+        assert_relative_eq!(in_water.ior(&Vector::zeros()), 1.33);
+
+        assert!(in_water.is_boundary(&hit(&water, false)));
+        let out = in_water.crossed(&hit(&water, false));
+        assert_relative_eq!(out.ior(&Vector::zeros()), 1.);
+
+        assert!(out.crossed(&hit(&water, false)).inside.is_empty());
+    }
+
+    #[test]
+    fn test_holes_are_not_entered() {
+        let glass = Material { ior: 1.5, transmission: true, ..Material::default() };
+        let outside = DielectricStack::new();
+
+        assert!(outside.cut_through(&hit(&glass, true)).inside.is_empty());
+        let in_glass = outside.crossed(&hit(&glass, true));
+        assert!(in_glass.cut_through(&hit(&glass, false)).inside.is_empty());
+    }
+}
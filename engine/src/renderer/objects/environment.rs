@@ -0,0 +1,233 @@
+use std::f64::consts::TAU;
+
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::environment_map::EnvironmentMap;
+use crate::renderer::objects::hit::Hit;
+use crate::renderer::objects::light::DirectionalLight;
+use crate::renderer::objects::model::Model;
+use crate::renderer::objects::ray::{Ray, RgbIntensity, Unit, Vector, Vector3};
+use crate::renderer::objects::sky::PhysicalSky;
+use crate::renderer::scene::Scene;
+
+/// Sky blending from `horizon` straight out to `zenith` straight up, z being up, over a
+/// uniform `ground`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Gradient {
+    pub zenith: RgbIntensity,
+    pub horizon: RgbIntensity,
+    pub ground: RgbIntensity,
+}
+
+impl Gradient {
+    fn evaluate(&self, direction: &Vector) -> RgbIntensity {
+        let height = direction.normalize().z as f32;
+        if height < 0. { self.ground } else { self.horizon.lerp(&self.zenith, height) }
+    }
+}
+
+/// What rays leaving the scene see.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Background {
+    Constant { color: RgbIntensity },
+    Gradient(Gradient),
+    Sky(PhysicalSky),
+    Map(EnvironmentMap),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Constant { color: RgbIntensity::zeros() }
+    }
+}
+
+impl Background {
+    pub fn evaluate(&self, direction: &Vector) -> RgbIntensity {
+        match self {
+            Background::Constant { color } => *color,
+            Background::Gradient(gradient) => gradient.evaluate(direction),
+            Background::Sky(sky) => sky.evaluate(direction),
+            Background::Map(map) => map.evaluate(direction),
+        }
+    }
+
+    /// Light from the sun of a sky that asks for it.
+    pub fn sun(&self) -> Option<DirectionalLight> {
+        match self {
+            Background::Sky(sky) => sky.sun_light(),
+            _ => None,
+        }
+    }
+
+    /// Direction for `u, v` in `[0, 1)` with its probability density over solid angle. `None`
+    /// leaves the background to rays escaping the scene.
+    pub fn sample(&self, u: f64, v: f64) -> Option<(Unit, f64)> {
+        match self {
+            Background::Map(map) => Some(map.sample(u, v)),
+            _ => None,
+        }
+    }
+
+    /// Density of `sample` returning `direction`.
+    pub fn pdf(&self, direction: &Vector) -> f64 {
+        match self {
+            Background::Map(map) => map.pdf(direction),
+            _ => 0.,
+        }
+    }
+}
+
+/// Darkening of the fill where nearby geometry hides the background.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Occlusion {
+    /// Objects further away than this don't occlude.
+    pub distance: f64,
+    /// Rays per shading point.
+    #[serde(default = "Occlusion::default_samples")]
+    pub samples: usize,
+}
+
+impl Occlusion {
+    const EPSILON: f64 = 1e-6;
+
+    fn default_samples() -> usize {
+        16
+    }
+
+    /// Share of cosine weighted rays from `hit` getting `distance` away without hitting
+    /// anything, for `random` pairs of numbers in `[0, 1)`.
+    fn open<M: Model>(&self, scene: &Scene<M>, hit: &Hit, mut random: impl FnMut() -> (f64, f64)) -> f32 {
+        let samples = self.samples.max(1);
+        let open = (0..samples)
+            .filter(|_| {
+                let (u, v) = random();
+                let direction = cosine_direction(&hit.shading_normal(), u, v);
+                let ray = Ray::new(hit.pos + direction.scale(Self::EPSILON), direction, 1.);
+                scene.intersect(&ray).is_none_or(|blocker| blocker.factor > self.distance)
+            })
+            .count();
+        open as f32 / samples as f32
+    }
+}
+
+/// Direction around `normal` for `u, v` in `[0, 1)`, distributed by the cosine to it.
+fn cosine_direction(normal: &Unit, u: f64, v: f64) -> Unit {
+    let normal = normal.xyz();
+    let helper = if normal.z.abs() < 0.9 { Vector3::z() } else { Vector3::x() };
+    let tangent = helper.cross(&normal).normalize();
+    let bitangent = normal.cross(&tangent);
+    let (radius, phi) = (u.sqrt(), TAU * v);
+    let direction = tangent * (radius * phi.cos()) + bitangent * (radius * phi.sin()) + normal * (1. - u).sqrt();
+    Unit::new_normalize(direction.to_homogeneous())
+}
+
+/// Ambient light added on every surface, apart from what the renderer gathers from the
+/// background, to lift shadows.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Fill {
+    #[serde(default)]
+    pub color: RgbIntensity,
+    /// Share of the background seen along the surface normal added to `color`.
+    #[serde(default)]
+    pub background: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occlusion: Option<Occlusion>,
+}
+
+impl Fill {
+    pub fn constant(color: RgbIntensity) -> Self {
+        Fill { color, ..Default::default() }
+    }
+
+    pub fn with_occlusion(mut self, distance: f64, samples: usize) -> Self {
+        self.occlusion = Some(Occlusion { distance, samples });
+        self
+    }
+}
+
+/// Surroundings of a scene shared by all renderers: the background rays escape to and a
+/// separate fill term for surfaces.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Environment {
+    #[serde(default)]
+    pub background: Background,
+    #[serde(default)]
+    pub fill: Fill,
+}
+
+impl Environment {
+    pub fn new(background: Background) -> Self {
+        Environment { background, fill: Fill::default() }
+    }
+
+    pub fn constant(color: RgbIntensity) -> Self {
+        Self::new(Background::Constant { color })
+    }
+
+    pub fn with_fill(mut self, fill: Fill) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    /// Fill light reaching `hit`, the material color excluded. `random` gives the pairs of
+    /// numbers in `[0, 1)` the occlusion rays are made of.
+    pub fn fill<M: Model>(&self, scene: &Scene<M>, hit: &Hit, random: impl FnMut() -> (f64, f64)) -> RgbIntensity {
+        let light = self.fill.color + self.background.evaluate(&hit.shading_normal()) * self.fill.background;
+        match &self.fill.occlusion {
+            Some(occlusion) if light.max() > 0. => light * occlusion.open(scene, hit, random),
+            _ => light,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::{Background, Environment, Fill};
+    use crate::renderer::objects::material::MaterialBuilder;
+    use crate::renderer::objects::model::sphere::SphereModel;
+    use crate::renderer::objects::ray::{Ray, RgbIntensity, Unit, Vector};
+    use crate::renderer::scene::Scene;
+
+    #[test]
+    fn test_environment_from_yaml() {
+        let environment: Environment = serde_yaml::from_str(
+            "background: {type: gradient, zenith: [0, 0, 1], horizon: [1, 1, 1], ground: [0.2, 0.2, 0.2]}\n\
+             fill: {color: [0.1, 0.1, 0.1], occlusion: {distance: 2}}\n",
+        )
+        .unwrap();
+        let background = &environment.background;
+        assert_relative_eq!(background.evaluate(&Vector::new(0., 0., 2., 0.)), RgbIntensity::new(0., 0., 1.));
+        assert_relative_eq!(background.evaluate(&Vector::new(1., 0., 0., 0.)), RgbIntensity::new(1., 1., 1.));
+        assert_relative_eq!(background.evaluate(&Vector::new(0., 1., -1., 0.)), RgbIntensity::new(0.2, 0.2, 0.2));
+        assert_eq!(environment.fill.occlusion.unwrap().samples, 16);
+
+        let sky: Background = serde_yaml::from_str("{type: sky, sun_elevation: 0.5}").unwrap();
+        assert!(sky.evaluate(&Vector::new(0., 0., 1., 0.)).z > 0.);
+        assert!(Environment::default().background.evaluate(&Vector::new(0., 0., 1., 0.)).max() == 0.);
+    }
+
+    #[test]
+    fn test_fill_is_occluded_under_a_roof() {
+        let matte = MaterialBuilder::default().build().unwrap();
+        let scene = Scene::new(vec![
+            SphereModel::new(Vector::new(0., 0., -1e5, 0.), 1e5, matte.clone()),
+            SphereModel::new(Vector::new(10., 0., 1001., 0.), 1000., matte),
+        ]);
+        let environment = Environment::constant(RgbIntensity::zeros()).with_fill(Fill::constant([0.5; 3].into()).with_occlusion(50., 64));
+        let fill_at = |x: f64| {
+            let ray = Ray::new(Vector::new(x, 0., 0.5, 0.), Unit::new_normalize(Vector::new(0.01, 0., -1., 0.)), 1.);
+            let hit = scene.intersect(&ray).unwrap();
+            let mut k = 0.;
+            environment.fill(&scene, &hit, || {
+                k += 1.;
+                ((k * 0.754877666) % 1., (k * 0.569840291) % 1.)
+            })
+        };
+
+        assert_relative_eq!(fill_at(-1500.).x, 0.5);
+        assert!(fill_at(10.).x < 0.05);
+    }
+}
@@ -0,0 +1,272 @@
+use std::error::Error;
+use std::f64::consts::{PI, TAU};
+use std::path::Path;
+use std::sync::Arc;
+
+use image::Rgb32FImage;
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::ray::{RgbIntensity, Unit, Vector};
+
+/// Piecewise-constant distribution over `0..n`, sampled by inverting its cumulative sum.
+#[derive(Debug)]
+struct Distribution {
+    cdf: Vec<f64>,
+    total: f64,
+}
+
+impl Distribution {
+    fn new(weights: impl Iterator<Item = f64>) -> Self {
+        let mut cdf = vec![0.];
+        for weight in weights {
+            cdf.push(cdf.last().unwrap() + weight.max(0.));
+        }
+        let total = *cdf.last().unwrap();
+        Distribution { cdf, total }
+    }
+
+    fn len(&self) -> usize {
+        self.cdf.len() - 1
+    }
+
+    /// Index for `u` in `[0, 1)` with its probability and where `u` fell within it, in `[0, 1)`.
+    fn sample(&self, u: f64) -> (usize, f64, f64) {
+        if self.total <= 0. {
+            let scaled = u * self.len() as f64;
+            let index = (scaled as usize).min(self.len() - 1);
+            return (index, 1. / self.len() as f64, scaled - index as f64);
+        }
+        let target = u * self.total;
+        let index = self.cdf.partition_point(|&c| c <= target).clamp(1, self.len()) - 1;
+        let offset = (target - self.cdf[index]) / (self.cdf[index + 1] - self.cdf[index]);
+        (index, self.probability(index), offset.clamp(0., 1.))
+    }
+
+    fn probability(&self, index: usize) -> f64 {
+        if self.total <= 0. {
+            return 1. / self.len() as f64;
+        }
+        (self.cdf[index + 1] - self.cdf[index]) / self.total
+    }
+}
+
+/// Rows and, within each row, columns of an environment map, weighted by luminance and by the
+/// solid angle of the pixels.
+#[derive(Debug)]
+struct PixelDistribution {
+    rows: Distribution,
+    columns: Vec<Distribution>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EnvironmentMapConfig {
+    file: String,
+    /// Turn around the z axis in radians.
+    #[serde(default)]
+    rotation: f64,
+    #[serde(default = "EnvironmentMapConfig::default_intensity")]
+    intensity: f32,
+}
+
+impl EnvironmentMapConfig {
+    fn default_intensity() -> f32 {
+        1.
+    }
+}
+
+/// Equirectangular image of the light coming from every direction, z being up. Loads Radiance
+/// `.hdr`, OpenEXR and PFM files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "EnvironmentMapConfig", into = "EnvironmentMapConfig")]
+pub struct EnvironmentMap {
+    file: String,
+    rotation: f64,
+    intensity: f32,
+    image: Arc<Rgb32FImage>,
+    distribution: Arc<PixelDistribution>,
+}
+
+fn luminance(color: &RgbIntensity) -> f64 {
+    (0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z) as f64
+}
+
+impl EnvironmentMap {
+    pub fn new(file: String, rotation: f64, intensity: f32) -> Result<Self, Box<dyn Error>> {
+        let is_pfm = Path::new(&file).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("pfm"));
+        let image = if is_pfm { Self::read_pfm(&std::fs::read(&file)?)? } else { image::open(&file)?.into_rgb32f() };
+        Ok(EnvironmentMap { file, ..Self::from_image(image, rotation, intensity) })
+    }
+
+    pub fn from_image(image: Rgb32FImage, rotation: f64, intensity: f32) -> Self {
+        let (width, height) = image.dimensions();
+        let columns: Vec<Distribution> = (0..height)
+            .map(|y| Distribution::new((0..width).map(|x| luminance(&image.get_pixel(x, y).0.into()))))
+            .collect();
+        let rows = Distribution::new(columns.iter().enumerate().map(|(y, row)| {
+            row.total * ((y as f64 + 0.5) / height as f64 * PI).sin()
+        }));
+
+        EnvironmentMap {
+            file: String::new(),
+            rotation,
+            intensity,
+            image: image.into(),
+            distribution: PixelDistribution { rows, columns }.into(),
+        }
+    }
+
+    /// Portable float map: `PF` for color or `Pf` for gray, the size, then a scale whose sign
+    /// gives the byte order, and rows from the bottom up.
+    fn read_pfm(data: &[u8]) -> Result<Rgb32FImage, Box<dyn Error>> {
+        let mut header = Vec::new();
+        let mut start = 0;
+        while header.len() < 4 {
+            while start < data.len() && data[start].is_ascii_whitespace() {
+                start += 1;
+            }
+            let end = start + data[start..].iter().position(u8::is_ascii_whitespace).ok_or("truncated PFM header")?;
+            header.push(std::str::from_utf8(&data[start..end])?.to_string());
+            start = end;
+        }
+        let pixels = &data[start + 1..];
+
+        let channels = match header[0].as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            other => return Err(format!("not a PFM file: {}", other).into()),
+        };
+        let (width, height): (u32, u32) = (header[1].parse()?, header[2].parse()?);
+        let little_endian = header[3].parse::<f32>()? < 0.;
+        if pixels.len() < (width * height * channels * 4) as usize {
+            return Err("truncated PFM data".into());
+        }
+
+        let value = |i: usize| {
+            let bytes: [u8; 4] = pixels[4 * i..4 * i + 4].try_into().unwrap();
+            if little_endian { f32::from_le_bytes(bytes) } else { f32::from_be_bytes(bytes) }
+        };
+        Ok(Rgb32FImage::from_fn(width, height, |x, y| {
+            let first = (((height - 1 - y) * width + x) * channels) as usize;
+            let channel = |c: usize| value(first + c.min(channels as usize - 1));
+            image::Rgb([channel(0), channel(1), channel(2)])
+        }))
+    }
+
+    fn pixel_of(&self, direction: &Vector) -> (u32, u32) {
+        let (width, height) = self.image.dimensions();
+        let u = ((direction.y.atan2(direction.x) - self.rotation) / TAU).rem_euclid(1.);
+        let v = direction.z.clamp(-1., 1.).acos() / PI;
+        (
+            ((u * width as f64) as u32).min(width - 1),
+            ((v * height as f64) as u32).min(height - 1),
+        )
+    }
+
+    pub fn evaluate(&self, direction: &Vector) -> RgbIntensity {
+        let (x, y) = self.pixel_of(direction);
+        RgbIntensity::from(self.image.get_pixel(x, y).0) * self.intensity
+    }
+
+    /// Direction for `u, v` in `[0, 1)` drawn in proportion to luminance, with its probability
+    /// density over solid angle.
+    pub fn sample(&self, u: f64, v: f64) -> (Unit, f64) {
+        let (width, height) = self.image.dimensions();
+        let (y, row_probability, y_offset) = self.distribution.rows.sample(v);
+        let (x, column_probability, x_offset) = self.distribution.columns[y].sample(u);
+
+        let phi = (x as f64 + x_offset) / width as f64 * TAU + self.rotation;
+        let theta = (y as f64 + y_offset) / height as f64 * PI;
+        let direction = Unit::new_normalize(Vector::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos(), 0.));
+        (direction, self.density(theta, row_probability * column_probability))
+    }
+
+    /// Probability density over solid angle of `sample` returning `direction`.
+    pub fn pdf(&self, direction: &Vector) -> f64 {
+        let (x, y) = self.pixel_of(direction);
+        let probability = self.distribution.rows.probability(y as usize) * self.distribution.columns[y as usize].probability(x as usize);
+        self.density(direction.z.clamp(-1., 1.).acos(), probability)
+    }
+
+    /// Density over solid angle at polar angle `theta` within a pixel picked with `probability`.
+    fn density(&self, theta: f64, probability: f64) -> f64 {
+        let (width, height) = self.image.dimensions();
+        let jacobian = TAU / width as f64 * PI / height as f64 * theta.sin();
+        if jacobian <= 0. { 0. } else { probability / jacobian }
+    }
+}
+
+impl TryFrom<EnvironmentMapConfig> for EnvironmentMap {
+    type Error = String;
+
+    fn try_from(config: EnvironmentMapConfig) -> Result<Self, Self::Error> {
+        EnvironmentMap::new(config.file.clone(), config.rotation, config.intensity)
+            .map_err(|e| format!("failed to load environment map {}: {}", config.file, e))
+    }
+}
+
+impl From<EnvironmentMap> for EnvironmentMapConfig {
+    fn from(map: EnvironmentMap) -> Self {
+        EnvironmentMapConfig { file: map.file, rotation: map.rotation, intensity: map.intensity }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use image::{Rgb, Rgb32FImage};
+    use rand::{Rng, SeedableRng};
+
+    use super::EnvironmentMap;
+    use crate::renderer::objects::ray::{RgbIntensity, Vector};
+
+    /// Dim sky with a bright spot near the horizon.
+    fn sky() -> Rgb32FImage {
+        Rgb32FImage::from_fn(32, 16, |x, y| if (x, y) == (8, 7) { Rgb([50., 40., 30.]) } else { Rgb([0.2, 0.3, 0.5]) })
+    }
+
+    #[test]
+    fn test_rotation() {
+        let map = EnvironmentMap::from_image(sky(), std::f64::consts::FRAC_PI_2, 2.);
+        let phi = (8.5 / 32.) * std::f64::consts::TAU + std::f64::consts::FRAC_PI_2;
+        let theta = 7.5 / 16. * std::f64::consts::PI;
+        let towards_spot = Vector::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos(), 0.);
+
+        assert_relative_eq!(map.evaluate(&towards_spot), RgbIntensity::new(100., 80., 60.));
+        assert_relative_eq!(map.evaluate(&Vector::new(0., 0., 1., 0.)), RgbIntensity::new(0.4, 0.6, 1.));
+    }
+
+    #[test]
+    fn test_importance_sampling_is_unbiased() {
+        let map = EnvironmentMap::from_image(sky(), 0.3, 1.);
+        let (width, height) = (32., 16.);
+        let expected: f64 = sky()
+            .enumerate_pixels()
+            .map(|(_, y, pixel)| {
+                let sin = ((y as f64 + 0.5) / height * std::f64::consts::PI).sin();
+                pixel.0[0] as f64 * std::f64::consts::TAU / width * std::f64::consts::PI / height * sin
+            })
+            .sum();
+
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(1);
+        let n = 20_000;
+        let estimate: f64 = (0..n)
+            .map(|_| {
+                let (direction, pdf) = map.sample(rng.random(), rng.random());
+                assert_relative_eq!(map.pdf(&direction), pdf, max_relative = 1e-6);
+                map.evaluate(&direction).x as f64 / pdf
+            })
+            .sum::<f64>()
+            / n as f64;
+        assert_relative_eq!(estimate, expected, max_relative = 1e-2);
+    }
+
+    #[test]
+    fn test_pfm() {
+        let mut data = b"PF\n2 1\n-1.0\n".to_vec();
+        for value in [1f32, 2., 3., 4., 5., 6.] {
+            data.extend(value.to_le_bytes());
+        }
+        let image = EnvironmentMap::read_pfm(&data).unwrap();
+        assert_eq!(image.get_pixel(1, 0).0, [4., 5., 6.]);
+    }
+}
@@ -1,13 +1,24 @@
 use nalgebra::Unit;
-use crate::renderer::objects::material::Material;
-use crate::renderer::objects::ray::Vector;
+use crate::renderer::objects::material::{Cutout, Material, RgbIntensity};
+use crate::renderer::objects::ray::{Ray, Uv, Vector, Vector3};
+use crate::renderer::objects::texture::Texture;
 
 #[derive(Debug, Clone)]
 pub struct Hit<'a> {
     pub factor: f64,
     pub pos: Vector,
     pub material: &'a Material,
+    /// Shading normal, with normal and bump maps applied. Points out of the surface.
     pub normal: Unit<Vector>,
+    /// Normal of the actual surface, pointing out of it.
+    pub geometric_normal: Unit<Vector>,
+    /// Whether the ray came from the outside of the surface.
+    pub front_face: bool,
+    pub uv: Uv,
+    /// Direction of increasing `u` on the surface, orthogonal to `normal`.
+    pub tangent: Unit<Vector>,
+    /// Index of the object in the scene, set by the scene.
+    pub object: usize,
 }
 
 impl<'a> Hit<'a> {
@@ -16,7 +27,138 @@ impl<'a> Hit<'a> {
         pos: Vector,
         material: &'a Material,
         normal: Unit<Vector>,
+        uv: Uv,
+        tangent: Unit<Vector>,
     ) -> Self {
-        Hit { factor, pos, material, normal }
+        Hit { factor, pos, material, normal, geometric_normal: normal, front_face: true, uv, tangent, object: 0 }
+    }
+
+    /// Sets `front_face` for a ray going along `direction`.
+    pub fn facing(mut self, direction: &Unit<Vector>) -> Self {
+        self.front_face = self.geometric_normal.dot(direction) < 0.;
+        self
+    }
+
+    /// `normal` turned to the side the ray came from, so open surfaces seen from behind are lit
+    /// and scatter light on that side.
+    pub fn shading_normal(&self) -> Unit<Vector> {
+        if self.front_face { self.normal } else { -self.normal }
+    }
+
+    /// Any unit vector orthogonal to `normal`, for surfaces without a natural `u` direction.
+    pub fn default_tangent(normal: &Unit<Vector>) -> Unit<Vector> {
+        let helper = if normal.x.abs() < 0.9 { Vector::x() } else { Vector::y() };
+        Self::orthogonalized(&helper, normal)
+    }
+
+    /// Gram-Schmidt of `direction` against `normal`, falling back to `default_tangent`.
+    pub fn orthogonalized(direction: &Vector, normal: &Unit<Vector>) -> Unit<Vector> {
+        let tangent = direction - normal.scale(normal.dot(direction));
+        if tangent.magnitude_squared() < 1e-12 {
+            return Self::default_tangent(normal);
+        }
+        Unit::new_normalize(tangent)
+    }
+
+    pub fn bitangent(&self) -> Unit<Vector> {
+        let n = Vector3::new(self.normal.x, self.normal.y, self.normal.z);
+        let t = Vector3::new(self.tangent.x, self.tangent.y, self.tangent.z);
+        Unit::new_normalize(n.cross(&t).to_homogeneous())
+    }
+
+    /// Applies the material normal and bump maps to `normal`.
+    pub fn perturbed(mut self) -> Self {
+        if let Some(normal_map) = &self.material.textures.normal {
+            self.normal = normal_map.perturb(&self);
+            self.tangent = Self::orthogonalized(&self.tangent, &self.normal);
+        }
+        if let Some(bump_map) = &self.material.textures.bump {
+            self.normal = bump_map.perturb(&self);
+            self.tangent = Self::orthogonalized(&self.tangent, &self.normal);
+        }
+        self
+    }
+
+    /// Weights of the mirror reflection and of the refracted ray, for a ray going into a medium
+    /// of index `ior`. A coating replaces `metallic` on transmissive materials and is layered
+    /// over it on opaque ones.
+    pub fn specular_weights(&self, ray: &Ray, ior: f64) -> (RgbIntensity, RgbIntensity) {
+        let metallic = self.metallic();
+        let transmittance = self.transmittance();
+
+        match &self.material.coating {
+            None => (metallic, transmittance),
+            Some(film) => {
+                let cos_incident = ray.direction.dot(&self.normal).abs();
+                let film = film.reflectance_rgb(cos_incident, ray.ior, ior);
+                let passed = RgbIntensity::from([1.; 3]) - film;
+                if self.material.transmission {
+                    (film, transmittance.component_mul(&passed))
+                } else {
+                    (film + metallic.component_mul(&passed), transmittance)
+                }
+            }
+        }
+    }
+
+    fn textured(&self, texture: &Option<Texture>, constant: RgbIntensity) -> RgbIntensity {
+        texture.as_ref().map_or(constant, |texture| texture.evaluate(&self.uv, &self.pos, &self.geometric_normal))
+    }
+
+    pub fn color(&self) -> RgbIntensity {
+        self.textured(&self.material.textures.color, self.material.color)
+    }
+
+    pub fn emissivity(&self) -> RgbIntensity {
+        self.textured(&self.material.textures.emissivity, self.material.emissivity)
+    }
+
+    pub fn metallic(&self) -> RgbIntensity {
+        self.textured(&self.material.textures.metallic, self.material.metallic)
+    }
+
+    pub fn roughness(&self) -> RgbIntensity {
+        self.textured(&self.material.textures.roughness, self.material.roughness)
+    }
+
+    pub fn transmittance(&self) -> RgbIntensity {
+        self.textured(&self.material.textures.transmittance, self.material.transmittance)
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.material.textures.opacity
+            .as_ref()
+            .map_or(self.material.opacity, |texture| texture.evaluate(&self.uv, &self.pos, &self.geometric_normal).mean())
+    }
+
+    /// Whether a ray going along `direction` passes through the surface at this point, the
+    /// same every time.
+    pub fn is_cut_out(&self, direction: &Unit<Vector>) -> bool {
+        self.is_cut_out_with(|| Self::hashed(&self.pos, direction))
+    }
+
+    /// Whether a ray passes through the surface at this point, stochastic cutouts drawing
+    /// a uniform number in `[0, 1)` from `random`.
+    pub fn is_cut_out_with(&self, random: impl FnOnce() -> f32) -> bool {
+        let opacity = self.opacity();
+        if opacity >= 1. {
+            return false;
+        }
+        match self.material.cutout {
+            Cutout::Stochastic => random() >= opacity,
+            Cutout::Threshold(threshold) => opacity < threshold,
+        }
+    }
+
+    /// Uniform number in `[0, 1)` decided by the point and direction alone.
+    pub fn hashed(pos: &Vector, direction: &Unit<Vector>) -> f32 {
+        let mut hash = 0x9e37_79b9_7f4a_7c15u64;
+        for value in pos.xyz().iter().chain(direction.xyz().iter()) {
+            hash ^= value.to_bits();
+            hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            hash ^= hash >> 31;
+        }
+        (hash >> 40) as f32 / (1u64 << 24) as f32
     }
 }
@@ -0,0 +1,322 @@
+pub mod area;
+pub mod ies;
+pub mod sampler;
+
+use std::f64::consts::TAU;
+
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::hit::Hit;
+use crate::renderer::objects::light::area::AreaLight;
+use crate::renderer::objects::light::ies::IesProfile;
+use crate::renderer::objects::ray::{RgbIntensity, Unit, Vector, Vector3};
+
+/// Scene objects picked by name, all of them by default.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ObjectSet {
+    /// Only these when given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+impl ObjectSet {
+    pub fn contains(&self, name: Option<&str>) -> bool {
+        let listed = |names: &Vec<String>| name.is_some_and(|name| names.iter().any(|n| n == name));
+        self.include.as_ref().is_none_or(listed) && !listed(&self.exclude)
+    }
+}
+
+/// Which objects a light lights up and which ones block it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LightLink {
+    #[serde(default)]
+    pub illuminate: ObjectSet,
+    #[serde(default)]
+    pub shadow: ObjectSet,
+}
+
+/// Light radiating from a small ball, falling off with the inverse square of the distance.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PointLight {
+    pub position: Vector,
+    pub color: RgbIntensity,
+    pub intensity: f32,
+    /// Added in quadrature to the distance so that the light stays finite up close.
+    #[serde(default)]
+    pub radius: f64,
+    /// Spread of the light over directions, `intensity` being its peak.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<IesProfile>,
+    #[serde(default)]
+    pub link: LightLink,
+}
+
+impl PointLight {
+    pub fn new(position: Vector, intensity: f32, color: RgbIntensity) -> Self {
+        PointLight {
+            position,
+            color,
+            intensity,
+            radius: 0.,
+            profile: None,
+            link: LightLink::default(),
+        }
+    }
+
+    pub fn with_radius(mut self, radius: f64) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn with_profile(mut self, profile: IesProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    fn falloff(&self, pos: &Vector) -> f32 {
+        1. / ((self.position - pos).magnitude_squared() + self.radius * self.radius) as f32
+    }
+
+    /// Light reaching `pos`, before any spot cone.
+    fn intensity_at(&self, pos: &Vector) -> RgbIntensity {
+        let spread = self.profile.as_ref().map_or(1., |profile| profile.intensity(&(pos - self.position)));
+        self.color * self.intensity * self.falloff(pos) * spread
+    }
+
+    /// Light sent out in all directions, before any spot cone.
+    fn power(&self) -> f64 {
+        let spread = self.profile.as_ref().map_or(1., IesProfile::average);
+        (self.color * self.intensity).mean() as f64 * 2. * TAU * spread
+    }
+}
+
+/// Point light shining into a cone around `direction`, fading smoothly between the inner and
+/// the outer half-angles in radians.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SpotLight {
+    #[serde(flatten)]
+    pub light: PointLight,
+    pub direction: Vector,
+    pub inner_angle: f64,
+    pub outer_angle: f64,
+}
+
+impl SpotLight {
+    fn cone(&self, pos: &Vector) -> f32 {
+        let cos = (pos - self.light.position).normalize().dot(&self.direction.normalize());
+        let (inner, outer) = (self.inner_angle.cos(), self.outer_angle.cos());
+        if inner <= outer {
+            return if cos >= outer { 1. } else { 0. };
+        }
+        let t = ((cos - outer) / (inner - outer)).clamp(0., 1.);
+        (t * t * (3. - 2. * t)) as f32
+    }
+}
+
+/// Light from far away, such as the sun, arriving along `direction` everywhere with
+/// `intensity` as the irradiance of a surface facing it. A non-zero angular diameter in
+/// radians softens the shadows.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DirectionalLight {
+    pub direction: Vector,
+    pub color: RgbIntensity,
+    pub intensity: f32,
+    #[serde(default)]
+    pub angular_diameter: f64,
+    /// Shadow rays per shading point in `GlobalIllumination` when the light has a size,
+    /// rounded up to a square grid.
+    #[serde(default = "DirectionalLight::default_samples")]
+    pub samples: usize,
+    #[serde(default)]
+    pub link: LightLink,
+}
+
+impl DirectionalLight {
+    /// How far away shadow rays look for the light.
+    const DISTANCE: f64 = 1e9;
+
+    fn default_samples() -> usize {
+        16
+    }
+
+    pub fn new(direction: Vector, intensity: f32, color: RgbIntensity) -> Self {
+        DirectionalLight {
+            direction,
+            color,
+            intensity,
+            angular_diameter: 0.,
+            samples: Self::default_samples(),
+            link: LightLink::default(),
+        }
+    }
+
+    /// Direction towards the light for `u, v` in `[0, 1)`, uniform over its disk on the sky.
+    fn towards(&self, u: f64, v: f64) -> Unit {
+        let axis = Unit::new_normalize(-self.direction);
+        let cos = 1. - u * (1. - (self.angular_diameter / 2.).cos());
+        let sin = (1. - cos * cos).max(0.).sqrt();
+        let tangent = Hit::default_tangent(&axis);
+        let bitangent = Vector3::new(axis.x, axis.y, axis.z)
+            .cross(&Vector3::new(tangent.x, tangent.y, tangent.z))
+            .to_homogeneous();
+        let phi = TAU * v;
+        Unit::new_normalize(axis.scale(cos) + tangent.scale(sin * phi.cos()) + bitangent.scale(sin * phi.sin()))
+    }
+}
+
+/// Light source apart from the scene geometry, seen only through the light it casts.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Light {
+    Point(PointLight),
+    Spot(SpotLight),
+    Directional(DirectionalLight),
+    Area(AreaLight),
+}
+
+impl Light {
+    /// Light sent out in all directions, infinite for directional lights.
+    pub fn power(&self) -> f64 {
+        match self {
+            Light::Point(light) => light.power(),
+            Light::Spot(spot) => spot.light.power() * (1. - spot.outer_angle.cos()) / 2.,
+            Light::Directional(_) => f64::INFINITY,
+            Light::Area(light) => light.power(),
+        }
+    }
+
+    /// Shadow rays `GlobalIllumination` spends on the light per shading point.
+    pub fn samples(&self) -> usize {
+        match self {
+            Light::Point(_) | Light::Spot(_) => 1,
+            Light::Directional(light) if light.angular_diameter > 0. => light.samples,
+            Light::Directional(_) => 1,
+            Light::Area(light) => light.samples,
+        }
+    }
+
+    pub fn link(&self) -> &LightLink {
+        match self {
+            Light::Point(light) => &light.link,
+            Light::Spot(spot) => &spot.light.link,
+            Light::Directional(light) => &light.link,
+            Light::Area(light) => &light.link,
+        }
+    }
+
+    /// Light of the sample at `u, v` in `[0, 1)` reaching `pos`: the point it leaves from
+    /// and the intensity it brings to a surface facing it, as if it stood for the whole light.
+    /// `None` when no light gets to `pos` that way.
+    pub fn illuminate(&self, u: f64, v: f64, pos: &Vector) -> Option<(Vector, RgbIntensity)> {
+        match self {
+            Light::Point(light) => Some((light.position, light.intensity_at(pos))),
+            Light::Spot(spot) => {
+                let cone = spot.cone(pos);
+                (cone > 0.).then(|| (spot.light.position, spot.light.intensity_at(pos) * cone))
+            }
+            Light::Directional(light) => Some((
+                pos + light.towards(u, v).scale(DirectionalLight::DISTANCE),
+                light.color * light.intensity,
+            )),
+            Light::Area(light) => light.illuminate(u, v, pos),
+        }
+    }
+}
+
+impl From<PointLight> for Light {
+    fn from(light: PointLight) -> Self {
+        Light::Point(light)
+    }
+}
+
+impl From<DirectionalLight> for Light {
+    fn from(light: DirectionalLight) -> Self {
+        Light::Directional(light)
+    }
+}
+
+impl From<AreaLight> for Light {
+    fn from(light: AreaLight) -> Self {
+        Light::Area(light)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::{Light, LightLink, ObjectSet, PointLight};
+    use crate::renderer::objects::light::ies::IesProfile;
+    use crate::renderer::objects::ray::Vector;
+
+    #[test]
+    fn test_lights_from_yaml() {
+        let lights: Vec<Light> = serde_yaml::from_str(
+            "- {type: point, position: [0, 0, 5, 0], color: [1, 1, 1], intensity: 10}\n\
+             - {type: area, shape: disk, center: [0, 0, 5, 0], normal: [0, 0, -1, 0], radius: 0.5, color: [1, 1, 1], intensity: 2}\n\
+             - {type: spot, position: [0, 0, 5, 0], direction: [0, 0, -1, 0], inner_angle: 0.3, outer_angle: 0.5, color: [1, 1, 1], intensity: 10}\n\
+             - {type: directional, direction: [0, 0, -1, 0], angular_diameter: 0.01, color: [1, 1, 1], intensity: 3}\n",
+        )
+        .unwrap();
+
+        assert!(matches!(lights[0], Light::Point(_)));
+        assert!(matches!(&lights[1], Light::Area(light) if light.samples == 16));
+        assert!(matches!(lights[2], Light::Spot(_)));
+        assert!(matches!(lights[3], Light::Directional(_)));
+    }
+
+    #[test]
+    fn test_object_sets() {
+        let link: LightLink = serde_yaml::from_str("{illuminate: {include: [table, vase]}, shadow: {exclude: [vase]}}").unwrap();
+        assert!(link.illuminate.contains(Some("vase")));
+        assert!(!link.illuminate.contains(Some("floor")));
+        assert!(!link.illuminate.contains(None));
+        assert!(!link.shadow.contains(Some("vase")));
+        assert!(link.shadow.contains(Some("table")) && link.shadow.contains(None));
+        assert!(ObjectSet::default().contains(None));
+    }
+
+    #[test]
+    fn test_inverse_square_falloff() {
+        let light = Light::from(PointLight::new(Vector::new(0., 0., 4., 0.), 16., [1.; 3].into()));
+        let (_, near) = light.illuminate(0.5, 0.5, &Vector::new(0., 0., 2., 0.)).unwrap();
+        let (_, far) = light.illuminate(0.5, 0.5, &Vector::zeros()).unwrap();
+        assert_relative_eq!(near.x, 4.);
+        assert_relative_eq!(far.x, 1.);
+
+        let soft = Light::from(PointLight::new(Vector::zeros(), 1., [1.; 3].into()).with_radius(0.5));
+        assert_relative_eq!(soft.illuminate(0.5, 0.5, &Vector::zeros()).unwrap().1.x, 4.);
+    }
+
+    #[test]
+    fn test_profile_shapes_point_light() {
+        let profile = IesProfile::parse(
+            "TILT=NONE\n1 1000 1 3 1 1 2 0 0 0\n1 1 100\n0 45 90\n0\n1000 500 0\n",
+            Vector::zeros(),
+        )
+        .unwrap();
+        let light = Light::from(PointLight::new(Vector::zeros(), 8., [1.; 3].into()).with_profile(profile));
+
+        assert_relative_eq!(light.illuminate(0.5, 0.5, &Vector::new(0., 0., -2., 0.)).unwrap().1.x, 2.);
+        assert_eq!(light.illuminate(0.5, 0.5, &Vector::new(0., 0., 2., 0.)).unwrap().1.x, 0.);
+        assert!(light.power() < 8. * 4. * std::f64::consts::PI / 2.);
+    }
+
+    #[test]
+    fn test_spot_cone() {
+        let lights: Vec<Light> = serde_yaml::from_str(
+            "- {type: spot, position: [0, 0, 0, 0], direction: [0, 0, -1, 0], inner_angle: 0.2, outer_angle: 0.4, color: [1, 1, 1], intensity: 1}\n",
+        )
+        .unwrap();
+        let spot = &lights[0];
+
+        let at = |angle: f64| {
+            spot.illuminate(0.5, 0.5, &Vector::new(angle.sin(), 0., -angle.cos(), 0.)).map_or(0., |(_, i)| i.x)
+        };
+        assert_relative_eq!(at(0.1), 1., epsilon = 1e-6);
+        assert!(at(0.3) > 0. && at(0.3) < 1.);
+        assert_eq!(at(0.5), 0.);
+    }
+}
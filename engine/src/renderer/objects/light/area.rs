@@ -0,0 +1,166 @@
+use std::f64::consts::{PI, TAU};
+
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::hit::Hit;
+use crate::renderer::objects::light::LightLink;
+use crate::renderer::objects::ray::{RgbIntensity, Unit, Vector, Vector3};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum AreaShape {
+    /// Parallelogram spanned by the full edges `edge_u` and `edge_v`, lit on the side of
+    /// `edge_u × edge_v`.
+    Rectangle { center: Vector, edge_u: Vector, edge_v: Vector },
+    /// Lit on the side of `normal`.
+    Disk { center: Vector, normal: Vector, radius: f64 },
+    Sphere { center: Vector, radius: f64 },
+}
+
+fn cross(a: &Vector, b: &Vector) -> Vector {
+    Vector3::new(a.x, a.y, a.z).cross(&Vector3::new(b.x, b.y, b.z)).to_homogeneous()
+}
+
+impl AreaShape {
+    /// Point for `u, v` in `[0, 1)`, with the normal there and the area the points are spread
+    /// over. Spheres only sample the half facing `toward`.
+    pub fn sample(&self, u: f64, v: f64, toward: &Vector) -> (Vector, Unit, f64) {
+        match self {
+            AreaShape::Rectangle { center, edge_u, edge_v } => {
+                let normal = cross(edge_u, edge_v);
+                (
+                    center + edge_u.scale(u - 0.5) + edge_v.scale(v - 0.5),
+                    Unit::new_normalize(normal),
+                    normal.magnitude(),
+                )
+            }
+            AreaShape::Disk { center, normal, radius } => {
+                let normal = Unit::new_normalize(*normal);
+                let tangent = Hit::default_tangent(&normal);
+                let bitangent = cross(&normal, &tangent);
+                let (r, phi) = (radius * u.sqrt(), TAU * v);
+                (
+                    center + tangent.scale(r * phi.cos()) + bitangent.scale(r * phi.sin()),
+                    normal,
+                    PI * radius * radius,
+                )
+            }
+            AreaShape::Sphere { center, radius } => {
+                let axis = Unit::try_new(toward - center, 1e-12).unwrap_or(Unit::new_unchecked(Vector::z()));
+                let tangent = Hit::default_tangent(&axis);
+                let bitangent = cross(&axis, &tangent);
+                let (z, phi) = (u, TAU * v);
+                let r = (1. - z * z).max(0.).sqrt();
+                let normal = Unit::new_normalize(
+                    axis.scale(z) + tangent.scale(r * phi.cos()) + bitangent.scale(r * phi.sin()),
+                );
+                (center + normal.scale(*radius), normal, TAU * radius * radius)
+            }
+        }
+    }
+
+    /// Area of the whole surface.
+    pub fn area(&self) -> f64 {
+        match self {
+            AreaShape::Rectangle { edge_u, edge_v, .. } => cross(edge_u, edge_v).magnitude(),
+            AreaShape::Disk { radius, .. } => PI * radius * radius,
+            AreaShape::Sphere { radius, .. } => 2. * TAU * radius * radius,
+        }
+    }
+
+    /// Corners of a box around the shape.
+    pub fn bounds(&self) -> (Vector, Vector) {
+        let (center, half) = match self {
+            AreaShape::Rectangle { center, edge_u, edge_v } => (center, (edge_u.abs() + edge_v.abs()) / 2.),
+            AreaShape::Disk { center, radius, .. } | AreaShape::Sphere { center, radius } => {
+                (center, Vector::new(*radius, *radius, *radius, 0.))
+            }
+        };
+        (center - half, center + half)
+    }
+}
+
+/// Emitting rectangle, disk or sphere casting soft shadows. It is not part of the scene, so
+/// rays never hit it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AreaLight {
+    #[serde(flatten)]
+    pub shape: AreaShape,
+    pub color: RgbIntensity,
+    /// Radiance scale of the surface.
+    pub intensity: f32,
+    /// Shadow rays per shading point in `GlobalIllumination`, rounded up to a square grid.
+    #[serde(default = "AreaLight::default_samples")]
+    pub samples: usize,
+    #[serde(default)]
+    pub link: LightLink,
+}
+
+impl AreaLight {
+    fn default_samples() -> usize {
+        16
+    }
+
+    pub fn new(shape: AreaShape, intensity: f32, color: RgbIntensity) -> Self {
+        AreaLight { shape, color, intensity, samples: Self::default_samples(), link: LightLink::default() }
+    }
+
+    /// Light leaving the whole surface, out of its lit side.
+    pub fn power(&self) -> f64 {
+        (self.color * self.intensity).mean() as f64 * self.shape.area() * PI
+    }
+
+    /// Light of the point sampled at `u, v` reaching `pos`: where it leaves from and its
+    /// radiance times the solid angle of the whole light as seen along it, leaving the cosine
+    /// at the receiving surface to the caller. `None` when `pos` is behind the light.
+    pub fn illuminate(&self, u: f64, v: f64, pos: &Vector) -> Option<(Vector, RgbIntensity)> {
+        let (point, normal, area) = self.shape.sample(u, v, pos);
+        let to_light = point - pos;
+        let distance_sq = to_light.magnitude_squared();
+        let cos_light = -normal.dot(&to_light) / distance_sq.sqrt();
+
+        (cos_light > 0.).then(|| (point, self.color * self.intensity * (cos_light * area / distance_sq) as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::{AreaLight, AreaShape};
+    use crate::renderer::objects::ray::{RgbIntensity, Vector};
+
+    fn mean_light(light: &AreaLight, pos: &Vector, side: usize) -> RgbIntensity {
+        (0..side * side)
+            .filter_map(|i| {
+                let u = ((i % side) as f64 + 0.5) / side as f64;
+                let v = ((i / side) as f64 + 0.5) / side as f64;
+                light.illuminate(u, v, pos).map(|(_, irradiance)| irradiance)
+            })
+            .sum::<RgbIntensity>()
+            / (side * side) as f32
+    }
+
+    #[test]
+    fn test_rectangle_is_one_sided() {
+        let light = AreaLight::new(
+            AreaShape::Rectangle {
+                center: Vector::new(0., 0., 10., 0.),
+                edge_u: Vector::new(0.1, 0., 0., 0.),
+                edge_v: Vector::new(0., -0.1, 0., 0.),
+            },
+            1.,
+            [1.; 3].into(),
+        );
+        assert_relative_eq!(mean_light(&light, &Vector::zeros(), 4).x, 1e-4, epsilon = 1e-7);
+        assert!(light.illuminate(0.5, 0.5, &Vector::new(0., 0., 20., 0.)).is_none());
+    }
+
+    #[test]
+    fn test_sphere_covers_its_solid_angle() {
+        let (radius, distance) = (1., 4.);
+        let light = AreaLight::new(AreaShape::Sphere { center: Vector::new(0., distance, 0., 0.), radius }, 1., [1.; 3].into());
+        let solid_angle = std::f64::consts::TAU * (1. - (1. - (radius / distance).powi(2)).sqrt());
+        assert_relative_eq!(mean_light(&light, &Vector::zeros(), 64).x as f64, solid_angle, max_relative = 1e-2);
+    }
+}
@@ -0,0 +1,226 @@
+use std::error::Error;
+use std::f64::consts::TAU;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::ray::{Matrix, Vector, Vector3};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IesProfileConfig {
+    file: String,
+    #[serde(default)]
+    rotation: Vector,
+}
+
+/// Candela values of an IES LM-63 file, on a grid of vertical angles from the nadir and
+/// horizontal angles around it, in degrees.
+#[derive(Debug)]
+struct Photometry {
+    vertical: Vec<f64>,
+    horizontal: Vec<f64>,
+    /// Per horizontal angle, per vertical angle, over the brightest value.
+    candela: Vec<Vec<f64>>,
+}
+
+/// Index of the segment of the sorted `angles` holding `angle` and how far along it is.
+fn segment(angles: &[f64], angle: f64) -> (usize, f64) {
+    let upper = angles.partition_point(|&a| a <= angle).clamp(1, angles.len() - 1);
+    let (a, b) = (angles[upper - 1], angles[upper]);
+    (upper - 1, if b > a { ((angle - a) / (b - a)).clamp(0., 1.) } else { 0. })
+}
+
+impl Photometry {
+    fn parse(data: &str) -> Result<Self, Box<dyn Error>> {
+        let mut lines = data.lines();
+        let tilt = lines
+            .find_map(|line| line.trim().strip_prefix("TILT="))
+            .ok_or("no TILT line in IES file")?;
+        let mut numbers = lines
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| token.parse::<f64>());
+        let mut next = || -> Result<f64, Box<dyn Error>> { Ok(numbers.next().ok_or("truncated IES file")??) };
+
+        // Tilt data only matters for lamps that change output when the fixture is tilted.
+        if tilt.trim() == "INCLUDE" {
+            next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+
+        let header: Vec<f64> = (0..13).map(|_| next()).collect::<Result<_, _>>()?;
+        let (vertical_count, horizontal_count) = (header[3] as usize, header[4] as usize);
+        if header[5] != 1. {
+            return Err(format!("only type C photometry is supported, got type {}", header[5]).into());
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err("IES file without angles".into());
+        }
+
+        let vertical: Vec<f64> = (0..vertical_count).map(|_| next()).collect::<Result<_, _>>()?;
+        let horizontal: Vec<f64> = (0..horizontal_count).map(|_| next()).collect::<Result<_, _>>()?;
+        let mut candela: Vec<Vec<f64>> = (0..horizontal_count)
+            .map(|_| (0..vertical_count).map(|_| next()).collect::<Result<_, _>>())
+            .collect::<Result<_, _>>()?;
+
+        let peak = candela.iter().flatten().fold(0., |peak: f64, &value| peak.max(value));
+        if peak > 0. {
+            candela.iter_mut().flatten().for_each(|value| *value /= peak);
+        }
+        Ok(Photometry { vertical, horizontal, candela })
+    }
+
+    /// Horizontal angle folded into the range the file covers, by its symmetry.
+    fn folded(&self, horizontal: f64) -> f64 {
+        match self.horizontal.last().copied().unwrap_or(0.) {
+            last if last <= 0. => 0.,
+            last if last <= 90. => {
+                let half = horizontal % 180.;
+                if half > 90. { 180. - half } else { half }
+            }
+            last if last <= 180. => if horizontal > 180. { 360. - horizontal } else { horizontal },
+            _ => horizontal,
+        }
+    }
+
+    fn along_plane(&self, plane: usize, vertical: f64) -> f64 {
+        let (i, t) = segment(&self.vertical, vertical);
+        let values = &self.candela[plane];
+        if values.len() == 1 { values[0] } else { values[i] * (1. - t) + values[i + 1] * t }
+    }
+
+    fn value(&self, vertical: f64, horizontal: f64) -> f64 {
+        let (first, last) = (self.vertical[0], *self.vertical.last().unwrap());
+        if vertical < first || vertical > last {
+            return 0.;
+        }
+        if self.horizontal.len() == 1 {
+            return self.along_plane(0, vertical);
+        }
+        let (i, t) = segment(&self.horizontal, self.folded(horizontal));
+        self.along_plane(i, vertical) * (1. - t) + self.along_plane(i + 1, vertical) * t
+    }
+}
+
+/// How a real fixture spreads its light, read from an IES LM-63 file with type C
+/// photometry. Unturned, the nadir of the profile points down -z and its 0° plane along x;
+/// `rotation` is an axis scaled by an angle in radians turning it from there.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "IesProfileConfig", into = "IesProfileConfig")]
+pub struct IesProfile {
+    file: String,
+    rotation: Vector,
+    /// Turns scene directions into the frame of the profile.
+    inverse: Matrix,
+    photometry: Arc<Photometry>,
+    average: f64,
+}
+
+impl IesProfile {
+    pub fn new(file: String, rotation: Vector) -> Result<Self, Box<dyn Error>> {
+        let profile = Self::parse(&std::fs::read_to_string(&file)?, rotation)?;
+        Ok(IesProfile { file, ..profile })
+    }
+
+    pub fn parse(data: &str, rotation: Vector) -> Result<Self, Box<dyn Error>> {
+        let mut profile = IesProfile {
+            file: String::new(),
+            rotation,
+            inverse: Matrix::new_rotation(Vector3::new(rotation.x, rotation.y, rotation.z)).transpose(),
+            photometry: Photometry::parse(data)?.into(),
+            average: 1.,
+        };
+        profile.average = profile.spherical_average();
+        Ok(profile)
+    }
+
+    /// Share of the peak intensity sent along `direction`.
+    pub fn intensity(&self, direction: &Vector) -> f32 {
+        let local = self.inverse * direction.normalize();
+        let vertical = (-local.z).clamp(-1., 1.).acos().to_degrees();
+        let horizontal = local.y.atan2(local.x).to_degrees().rem_euclid(360.);
+        self.photometry.value(vertical, horizontal) as f32
+    }
+
+    /// Mean of `intensity` over all directions.
+    pub fn average(&self) -> f64 {
+        self.average
+    }
+
+    /// `average` from a grid uniform over the sphere.
+    fn spherical_average(&self) -> f64 {
+        const STEPS: usize = 64;
+        let mut total = 0.;
+        for i in 0..STEPS {
+            let z = 1. - 2. * (i as f64 + 0.5) / STEPS as f64;
+            let r = (1. - z * z).sqrt();
+            for j in 0..2 * STEPS {
+                let phi = TAU * (j as f64 + 0.5) / (2 * STEPS) as f64;
+                total += self.intensity(&Vector::new(r * phi.cos(), r * phi.sin(), z, 0.)) as f64;
+            }
+        }
+        total / (2 * STEPS * STEPS) as f64
+    }
+}
+
+impl TryFrom<IesProfileConfig> for IesProfile {
+    type Error = String;
+
+    fn try_from(config: IesProfileConfig) -> Result<Self, Self::Error> {
+        IesProfile::new(config.file.clone(), config.rotation)
+            .map_err(|e| format!("failed to load IES profile {}: {}", config.file, e))
+    }
+}
+
+impl From<IesProfile> for IesProfileConfig {
+    fn from(profile: IesProfile) -> Self {
+        IesProfileConfig { file: profile.file, rotation: profile.rotation }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::IesProfile;
+    use crate::renderer::objects::ray::Vector;
+
+    /// Downlight lighting the lower half, brighter on the 90° side.
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002\n[TEST] downlight\nTILT=NONE\n\
+        1 1000 1 3 3 1 2 0 0 0\n1 1 100\n0 45 90\n0 90 180\n\
+        1000 500 0\n1000 1000 0\n1000 500 0\n";
+
+    #[test]
+    fn test_interpolation_and_symmetry() {
+        let profile = IesProfile::parse(DOWNLIGHT, Vector::zeros()).unwrap();
+        let at = |vertical: f64, horizontal: f64| {
+            let (vertical, horizontal) = (vertical.to_radians(), horizontal.to_radians());
+            profile.intensity(&Vector::new(
+                vertical.sin() * horizontal.cos(),
+                vertical.sin() * horizontal.sin(),
+                -vertical.cos(),
+                0.,
+            ))
+        };
+
+        assert_relative_eq!(at(0., 0.), 1.);
+        assert_relative_eq!(at(22.5, 0.), 0.75, epsilon = 1e-6);
+        assert_relative_eq!(at(45., 90.), 1., epsilon = 1e-6);
+        assert_relative_eq!(at(45., 45.), 0.75, epsilon = 1e-6);
+        // The 270° side mirrors the 90° one.
+        assert_relative_eq!(at(45., 270.), 1., epsilon = 1e-6);
+        assert_eq!(at(120., 0.), 0.);
+        assert!(profile.average() > 0.1 && profile.average() < 0.5);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let sideways = IesProfile::parse(DOWNLIGHT, Vector::new(0., std::f64::consts::FRAC_PI_2, 0., 0.)).unwrap();
+        assert_relative_eq!(sideways.intensity(&Vector::new(-1., 0., 0., 0.)), 1., epsilon = 1e-6);
+        assert_eq!(sideways.intensity(&Vector::new(0., 0., -1., 0.)), 0.);
+        assert!(IesProfile::parse("TILT=NONE\n1 1000 1 1 1 2 2 0 0 0\n1 1 100\n0\n0\n1\n", Vector::zeros()).is_err());
+    }
+}
@@ -0,0 +1,415 @@
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::light::Light;
+use crate::renderer::objects::light::area::AreaShape;
+use crate::renderer::objects::ray::{Vector, Vector3};
+
+/// Walker's alias method: picks an index in proportion to its weight in constant time.
+#[derive(Debug, Clone)]
+pub struct AliasTable {
+    /// Chance of keeping the drawn slot rather than taking its alias.
+    keep: Vec<f64>,
+    alias: Vec<usize>,
+    probabilities: Vec<f64>,
+}
+
+impl AliasTable {
+    /// Uniform when all weights are zero.
+    pub fn new(weights: &[f64]) -> Self {
+        let n = weights.len();
+        let total: f64 = weights.iter().map(|w| w.max(0.)).sum();
+        let probabilities: Vec<f64> = if total > 0. {
+            weights.iter().map(|w| w.max(0.) / total).collect()
+        } else {
+            vec![1. / n as f64; n]
+        };
+
+        let mut keep: Vec<f64> = probabilities.iter().map(|p| p * n as f64).collect();
+        let mut alias: Vec<usize> = (0..n).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).partition(|&i| keep[i] < 1.);
+        while let (Some(&less), Some(&more)) = (small.last(), large.last()) {
+            small.pop();
+            alias[less] = more;
+            keep[more] -= 1. - keep[less];
+            if keep[more] < 1. {
+                large.pop();
+                small.push(more);
+            }
+        }
+        // Leftovers are off from one by rounding only.
+        small.into_iter().chain(large).for_each(|i| keep[i] = 1.);
+
+        AliasTable { keep, alias, probabilities }
+    }
+
+    /// Index for `u` in `[0, 1)` with its probability.
+    pub fn sample(&self, u: f64) -> (usize, f64) {
+        let scaled = u * self.keep.len() as f64;
+        let slot = (scaled as usize).min(self.keep.len() - 1);
+        let index = if scaled - (slot as f64) < self.keep[slot] { slot } else { self.alias[slot] };
+        (index, self.probabilities[index])
+    }
+
+    pub fn probability(&self, index: usize) -> f64 {
+        self.probabilities[index]
+    }
+}
+
+fn to_vector3(vector: &Vector) -> Vector3 {
+    Vector3::new(vector.x, vector.y, vector.z)
+}
+
+fn angle(a: &Vector3, b: &Vector3) -> f64 {
+    a.dot(b).clamp(-1., 1.).acos()
+}
+
+/// Where a group of lights is, how much light it gives and which way, after Conty and Kulla:
+/// every emitting normal lies within `normal_spread` of `axis` and light leaves at most
+/// `emission_spread` away from the normal.
+#[derive(Debug, Clone)]
+struct LightBounds {
+    min: Vector3,
+    max: Vector3,
+    power: f64,
+    axis: Vector3,
+    normal_spread: f64,
+    emission_spread: f64,
+}
+
+impl LightBounds {
+    /// `None` for lights without a position.
+    fn of(light: &Light) -> Option<Self> {
+        let everywhere = |min: Vector3, max: Vector3| LightBounds {
+            min,
+            max,
+            power: light.power(),
+            axis: Vector3::z(),
+            normal_spread: PI,
+            emission_spread: FRAC_PI_2,
+        };
+        match light {
+            Light::Point(light) => Some(everywhere(to_vector3(&light.position), to_vector3(&light.position))),
+            Light::Spot(spot) => Some(LightBounds {
+                axis: to_vector3(&spot.direction).normalize(),
+                normal_spread: 0.,
+                emission_spread: spot.outer_angle.min(PI),
+                ..everywhere(to_vector3(&spot.light.position), to_vector3(&spot.light.position))
+            }),
+            Light::Directional(_) => None,
+            Light::Area(area) => {
+                let (min, max) = area.shape.bounds();
+                let normal = match &area.shape {
+                    AreaShape::Rectangle { edge_u, edge_v, .. } => to_vector3(edge_u).cross(&to_vector3(edge_v)),
+                    AreaShape::Disk { normal, .. } => to_vector3(normal),
+                    AreaShape::Sphere { .. } => return Some(everywhere(to_vector3(&min), to_vector3(&max))),
+                };
+                Some(LightBounds {
+                    axis: normal.normalize(),
+                    normal_spread: 0.,
+                    ..everywhere(to_vector3(&min), to_vector3(&max))
+                })
+            }
+        }
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        let (wide, narrow) = if self.normal_spread >= other.normal_spread { (self, other) } else { (other, self) };
+        let between = angle(&wide.axis, &narrow.axis);
+        let (axis, normal_spread) = if (between + narrow.normal_spread).min(PI) <= wide.normal_spread {
+            (wide.axis, wide.normal_spread)
+        } else {
+            let spread = (wide.normal_spread + between + narrow.normal_spread) / 2.;
+            let towards = narrow.axis - wide.axis.scale(wide.axis.dot(&narrow.axis));
+            match towards.try_normalize(1e-9) {
+                Some(towards) if spread < PI => {
+                    let turn = spread - wide.normal_spread;
+                    (wide.axis.scale(turn.cos()) + towards.scale(turn.sin()), spread)
+                }
+                _ => (wide.axis, PI),
+            }
+        };
+
+        LightBounds {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+            power: self.power + other.power,
+            axis,
+            normal_spread,
+            emission_spread: self.emission_spread.max(other.emission_spread),
+        }
+    }
+
+    fn center(&self) -> Vector3 {
+        (self.min + self.max) / 2.
+    }
+
+    /// Rough guess of how much of the light reaches `pos`, never zero where some might.
+    fn importance(&self, pos: &Vector3) -> f64 {
+        let radius = (self.max - self.min).magnitude() / 2.;
+        let offset = pos - self.center();
+        let distance = offset.magnitude();
+        if distance <= radius {
+            return self.power / (radius * radius / 4.).max(f64::MIN_POSITIVE);
+        }
+
+        let facing = angle(&self.axis, &(offset / distance));
+        let within = (radius / distance).asin();
+        let outside = (facing - self.normal_spread - within).max(0.);
+        if outside >= self.emission_spread {
+            return 0.;
+        }
+        self.power * outside.cos() / (distance * distance).max(radius * radius / 4.)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Leaf(usize),
+    Interior([usize; 2]),
+}
+
+/// Light BVH picking a light in proportion to an estimate of its importance at a point.
+#[derive(Debug, Clone)]
+struct LightTree {
+    nodes: Vec<(LightBounds, Node)>,
+}
+
+impl LightTree {
+    fn new(mut lights: Vec<(usize, LightBounds)>) -> Self {
+        let mut tree = LightTree { nodes: Vec::new() };
+        if !lights.is_empty() {
+            tree.build(&mut lights);
+        }
+        tree
+    }
+
+    /// Splits `lights` at the median along the axis their centers spread most, returning the
+    /// index of the node made for them.
+    fn build(&mut self, lights: &mut [(usize, LightBounds)]) -> usize {
+        if let [(index, bounds)] = lights {
+            self.nodes.push((bounds.clone(), Node::Leaf(*index)));
+            return self.nodes.len() - 1;
+        }
+
+        let (low, high) = lights.iter().fold((Vector3::repeat(f64::INFINITY), Vector3::repeat(f64::NEG_INFINITY)), |(low, high), (_, bounds)| {
+            (low.inf(&bounds.center()), high.sup(&bounds.center()))
+        });
+        let axis = (high - low).imax();
+        lights.sort_by(|a, b| a.1.center()[axis].total_cmp(&b.1.center()[axis]));
+
+        let (left, right) = lights.split_at_mut(lights.len() / 2);
+        let node = self.nodes.len();
+        self.nodes.push((left[0].1.clone(), Node::Interior([0, 0])));
+        let children = [self.build(left), self.build(right)];
+        self.nodes[node] = (self.nodes[children[0]].0.union(&self.nodes[children[1]].0), Node::Interior(children));
+        node
+    }
+
+    /// Light for `u` in `[0, 1)` with its probability, `None` when none can reach `pos`.
+    fn sample(&self, pos: &Vector, mut u: f64) -> Option<(usize, f64)> {
+        let pos = to_vector3(pos);
+        let (mut node, mut probability) = (0, 1.);
+        loop {
+            match &self.nodes.get(node)?.1 {
+                Node::Leaf(index) => return Some((*index, probability)),
+                Node::Interior([left, right]) => {
+                    let (a, b) = (self.nodes[*left].0.importance(&pos), self.nodes[*right].0.importance(&pos));
+                    if a + b <= 0. {
+                        return None;
+                    }
+                    let chance = a / (a + b);
+                    if u < chance {
+                        (node, probability, u) = (*left, probability * chance, u / chance);
+                    } else {
+                        (node, probability, u) = (*right, probability * (1. - chance), (u - chance) / (1. - chance));
+                    }
+                    u = u.min(1. - f64::EPSILON);
+                }
+            }
+        }
+    }
+}
+
+/// How `LightSampler` picks among the lights with a position.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightSelection {
+    /// All alike.
+    Uniform,
+    /// By power alone, the same everywhere.
+    Power,
+    /// By power, distance and orientation as seen from the shading point.
+    Tree,
+}
+
+/// Light sampling as set in a scene file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightSampling {
+    #[serde(default = "LightSampling::default_selection")]
+    pub selection: LightSelection,
+    /// Lights with a position each shading point takes.
+    #[serde(default = "LightSampling::default_picks")]
+    pub picks: usize,
+}
+
+impl LightSampling {
+    fn default_selection() -> LightSelection {
+        LightSelection::Tree
+    }
+
+    fn default_picks() -> usize {
+        1
+    }
+}
+
+/// Picks a few of many lights to shade a point with. Directional lights are always taken.
+#[derive(Debug, Clone)]
+pub struct LightSampler {
+    selection: LightSelection,
+    picks: usize,
+    distant: Vec<usize>,
+    local: Vec<usize>,
+    alias: AliasTable,
+    tree: LightTree,
+}
+
+impl LightSampler {
+    pub fn new(lights: &[Light], selection: LightSelection, picks: usize) -> Self {
+        let (bounded, distant): (Vec<_>, Vec<_>) = lights
+            .iter()
+            .enumerate()
+            .map(|(i, light)| (i, LightBounds::of(light)))
+            .partition(|(_, bounds)| bounds.is_some());
+        let (local, bounds): (Vec<usize>, Vec<LightBounds>) =
+            bounded.into_iter().map(|(i, bounds)| (i, bounds.unwrap())).unzip();
+
+        LightSampler {
+            selection,
+            picks: picks.max(1),
+            distant: distant.into_iter().map(|(i, _)| i).collect(),
+            local,
+            alias: AliasTable::new(&bounds.iter().map(|bounds| bounds.power).collect::<Vec<_>>()),
+            tree: LightTree::new(bounds.into_iter().enumerate().collect()),
+        }
+    }
+
+    pub fn picks(&self) -> usize {
+        self.picks
+    }
+
+    /// Indexes of the lights to shade `pos` with and the factor on each making up for the
+    /// ones left out. Draws `picks` lights with `random` numbers in `[0, 1)` when there are
+    /// more; the same light may come up more than once.
+    pub fn choose(&self, pos: &Vector, mut random: impl FnMut() -> f64) -> Vec<(usize, f32)> {
+        let mut chosen: Vec<(usize, f32)> = self.distant.iter().map(|&i| (i, 1.)).collect();
+        if self.local.len() <= self.picks {
+            chosen.extend(self.local.iter().map(|&i| (i, 1.)));
+            return chosen;
+        }
+
+        chosen.extend((0..self.picks).filter_map(|_| {
+            let (position, probability) = match self.selection {
+                LightSelection::Uniform => {
+                    let count = self.local.len();
+                    Some((((random() * count as f64) as usize).min(count - 1), 1. / count as f64))
+                }
+                LightSelection::Power => Some(self.alias.sample(random())),
+                LightSelection::Tree => self.tree.sample(pos, random()),
+            }?;
+            (probability > 0.).then(|| (self.local[position], (1. / (probability * self.picks as f64)) as f32))
+        }));
+        chosen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use rand::{Rng, SeedableRng};
+
+    use super::{AliasTable, LightSampler, LightSampling, LightSelection};
+    use crate::renderer::objects::light::{DirectionalLight, Light, PointLight};
+    use crate::renderer::objects::ray::Vector;
+
+    #[test]
+    fn test_alias_table_follows_weights() {
+        let table = AliasTable::new(&[1., 0., 3., 4.]);
+        let n = 80_000;
+        let mut counts = [0usize; 4];
+        for i in 0..n {
+            counts[table.sample((i as f64 + 0.5) / n as f64).0] += 1;
+        }
+        assert_eq!(counts[1], 0);
+        for (count, expected) in counts.iter().zip([0.125, 0., 0.375, 0.5]) {
+            assert_relative_eq!(*count as f64 / n as f64, expected, epsilon = 1e-3);
+        }
+    }
+
+    /// Row of lamps along x, one brighter than the rest, and the sun.
+    fn street() -> Vec<Light> {
+        let mut lights: Vec<Light> = (0..100)
+            .map(|i| PointLight::new(Vector::new(i as f64 * 3., 0., 4., 0.), if i == 7 { 50. } else { 10. }, [1.; 3].into()).into())
+            .collect();
+        lights.push(DirectionalLight::new(Vector::new(0., 0., -1., 0.), 1., [1.; 3].into()).into());
+        lights
+    }
+
+    fn exact(lights: &[Light], pos: &Vector) -> f64 {
+        lights.iter().map(|light| light.illuminate(0.5, 0.5, pos).unwrap().1.x as f64).sum()
+    }
+
+    #[test]
+    fn test_choices_are_unbiased() {
+        let lights = street();
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(3);
+        let pos = Vector::new(20., 1., 0., 0.);
+
+        for selection in [LightSelection::Uniform, LightSelection::Power, LightSelection::Tree] {
+            let sampler = LightSampler::new(&lights, selection, 2);
+            let n = 20_000;
+            let estimate = (0..n)
+                .map(|_| {
+                    let chosen = sampler.choose(&pos, || rng.random());
+                    assert!(chosen.iter().any(|&(i, weight)| i == 100 && weight == 1.));
+                    chosen
+                        .iter()
+                        .map(|&(i, weight)| lights[i].illuminate(0.5, 0.5, &pos).unwrap().1.x as f64 * weight as f64)
+                        .sum::<f64>()
+                })
+                .sum::<f64>()
+                / n as f64;
+            assert_relative_eq!(estimate, exact(&lights, &pos), max_relative = 2e-2);
+        }
+    }
+
+    #[test]
+    fn test_tree_prefers_nearby_lights() {
+        let lights = street();
+        let sampler = LightSampler::new(&lights, LightSelection::Tree, 1);
+        let pos = Vector::new(150., 0., 0., 0.);
+
+        let n = 1000;
+        let near = (0..n)
+            .filter(|i| {
+                let chosen = sampler.choose(&pos, || (*i as f64 + 0.5) / n as f64);
+                chosen.iter().any(|&(light, _)| match &lights[light] {
+                    Light::Point(point) => (point.position.x - pos.x).abs() < 10.,
+                    _ => false,
+                })
+            })
+            .count();
+        assert!(near as f64 / n as f64 > 0.5);
+    }
+
+    #[test]
+    fn test_light_sampling_from_yaml() {
+        let sampling: LightSampling = serde_yaml::from_str("selection: uniform").unwrap();
+        assert!(matches!(sampling.selection, LightSelection::Uniform));
+        assert_eq!(sampling.picks, 1);
+        let sampling: LightSampling = serde_yaml::from_str("picks: 4").unwrap();
+        assert!(matches!(sampling.selection, LightSelection::Tree));
+        assert_eq!(sampling.picks, 4);
+    }
+}
@@ -1,40 +1,190 @@
 #![allow(dead_code)]
 
+pub mod birefringence;
+pub mod coating;
+pub mod gradient_index;
+pub mod grating;
+pub mod library;
+pub mod subsurface;
+
 pub use crate::renderer::objects::ray::{RgbIntensity};
+use crate::renderer::objects::ray::Vector;
+use crate::renderer::objects::material::birefringence::Birefringence;
+use crate::renderer::objects::material::coating::ThinFilm;
+use crate::renderer::objects::material::gradient_index::IorField;
+use crate::renderer::objects::material::grating::DiffractionGrating;
+use crate::renderer::objects::medium::Medium;
+use crate::renderer::objects::material::subsurface::Subsurface;
+use crate::renderer::objects::polarization::LinearPolarizer;
+use crate::renderer::objects::texture::{BumpMap, NormalMap, Texture};
+use crate::renderer::objects::texture::procedural::Marble;
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 
+/// Per-parameter textures; a present texture replaces the constant value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaterialTextures {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<Texture>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emissivity: Option<Texture>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metallic: Option<Texture>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roughness: Option<Texture>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transmittance: Option<Texture>,
+    /// Opacity as the mean of the channels.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opacity: Option<Texture>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normal: Option<NormalMap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bump: Option<BumpMap>,
+}
+
+impl MaterialTextures {
+    pub fn is_empty(&self) -> bool {
+        self.color.is_none()
+            && self.emissivity.is_none()
+            && self.metallic.is_none()
+            && self.roughness.is_none()
+            && self.transmittance.is_none()
+            && self.opacity.is_none()
+            && self.normal.is_none()
+            && self.bump.is_none()
+    }
+}
+
+/// How a partially opaque surface decides where it is absent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cutout {
+    /// Absent with a probability of one minus the opacity, drawn by sampling renderers and
+    /// hashed from the hit point and the ray direction by deterministic ones.
+    #[default]
+    Stochastic,
+    /// Absent wherever the opacity is below the threshold.
+    Threshold(f32),
+}
+
 #[derive(Debug, Clone, Builder, Serialize, Deserialize)]
 pub struct Material {
     #[builder(default = RgbIntensity::from([0.; 3]))]
+    #[serde(default)]
     pub color: RgbIntensity,
 
     #[builder(default = RgbIntensity::from([0.; 3]))]
+    #[serde(default)]
     pub emissivity: RgbIntensity,
 
     #[builder(default = RgbIntensity::from([0.; 3]))]
+    #[serde(default)]
     pub metallic: RgbIntensity,
 
     #[builder(default = RgbIntensity::from([0.; 3]))]
+    #[serde(default)]
     pub roughness: RgbIntensity,
 
-    #[builder(default = RgbIntensity::from([0.; 3]))]
-    pub ambient: RgbIntensity,
-
-    #[builder(default = 1.)]
+    #[builder(default = Material::default_k())]
+    #[serde(default = "Material::default_k")]
     pub k: f64,
 
-    #[builder(default = 1.0)]
+    #[builder(default = Material::default_ior())]
+    #[serde(default = "Material::default_ior")]
     pub ior: f64,
     
     #[builder(default = false)]
+    #[serde(default)]
     pub transmission: bool,
+
+    /// Decides the medium where transmissive objects overlap; the highest priority wins.
+    #[builder(default)]
+    #[serde(default)]
+    pub priority: u32,
     
     #[builder(default = RgbIntensity::from([0.; 3]))]
+    #[serde(default)]
     pub transmittance: RgbIntensity,
+
+    /// Makes the surface invisible from the inside.
+    #[builder(default = false)]
+    #[serde(default)]
+    pub backface_culling: bool,
+
+    /// Fraction of rays the surface stops; the rest go through as if it was not there.
+    #[builder(default = Material::default_opacity())]
+    #[serde(default = "Material::default_opacity")]
+    pub opacity: f32,
+
+    #[builder(default)]
+    #[serde(default)]
+    pub cutout: Cutout,
+
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "MaterialTextures::is_empty")]
+    pub textures: MaterialTextures,
+
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coating: Option<ThinFilm>,
+
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subsurface: Option<Subsurface>,
+
+    /// Diffracts part of the mirror reflection into colored orders.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grating: Option<DiffractionGrating>,
+
+    /// Filters the light transmitted through the surface.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub polarizer: Option<LinearPolarizer>,
+
+    /// Splits refracted rays in two; replaces `ior` on the way in.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub birefringence: Option<Birefringence>,
+
+    /// Index varying through the inside; replaces `ior`.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gradient_index: Option<IorField>,
+
+    /// Fills the inside of a closed object.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub medium: Option<Medium>,
 }
 
 impl Material {
+    fn default_k() -> f64 {
+        1.
+    }
+
+    fn default_ior() -> f64 {
+        1.
+    }
+
+    fn default_opacity() -> f32 {
+        1.
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emissivity.max() > 0. || self.textures.emissivity.is_some()
+    }
+
+    /// Index of refraction just inside the surface at `pos`, the ordinary one for crystals.
+    pub fn ior_at(&self, pos: &Vector) -> f64 {
+        if let Some(crystal) = &self.birefringence {
+            return crystal.ordinary_ior;
+        }
+        self.gradient_index.as_ref().map_or(self.ior, |field| field.ior(pos))
+    }
+
     pub fn metallic() -> Self {
         MaterialBuilder::default()
             .color([0.5, 0.5, 0.7].into())
@@ -47,8 +197,11 @@ impl Material {
         MaterialBuilder::default()
             .color([0.8; 3].into())
             .roughness([0.7; 3].into())
-            .ambient([0.2; 3].into())
             .metallic([0.1; 3].into())
+            .textures(MaterialTextures {
+                color: Some(Texture::Marble(Marble::default())),
+                ..MaterialTextures::default()
+            })
             .build().unwrap()
     }
 }
@@ -61,10 +214,21 @@ impl Default for Material {
             metallic: RgbIntensity::from([0.3; 3]),
             roughness: RgbIntensity::from([0.2; 3]),
             transmittance: RgbIntensity::from([0.; 3]),
-            ambient: RgbIntensity::from([0.; 3]),
             k: 0.,
             ior: 1.,
             transmission: false,
+            priority: 0,
+            backface_culling: false,
+            opacity: 1.,
+            cutout: Cutout::default(),
+            textures: MaterialTextures::default(),
+            coating: None,
+            subsurface: None,
+            grating: None,
+            polarizer: None,
+            birefringence: None,
+            gradient_index: None,
+            medium: None,
         }
     }
 }
@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::ray::{Unit, Vector, Vector3};
+
+/// A uniaxial crystal such as calcite or quartz; refraction into it splits a ray into an
+/// ordinary and an extraordinary one, polarized at right angles to each other.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Birefringence {
+    pub ordinary_ior: f64,
+    pub extraordinary_ior: f64,
+    pub optic_axis: Vector,
+}
+
+impl Birefringence {
+    pub fn axis(&self) -> Unit {
+        Unit::new_normalize(self.optic_axis)
+    }
+
+    /// Index seen by an extraordinary wave travelling along `wave`.
+    pub fn index(&self, wave: &Unit) -> f64 {
+        let cos = wave.dot(&self.axis());
+        let sin_sq = 1. - cos * cos;
+        1. / (cos * cos / self.ordinary_ior.powi(2) + sin_sq / self.extraordinary_ior.powi(2)).sqrt()
+    }
+
+    /// Direction the energy of an extraordinary wave with wave vector `wave` flows in,
+    /// the normal of the index ellipsoid.
+    pub fn ray_direction(&self, wave: &Vector) -> Unit {
+        let axis = self.axis();
+        let along = axis.scale(wave.dot(&axis));
+        Unit::new_normalize((wave - along) / self.extraordinary_ior.powi(2) + along / self.ordinary_ior.powi(2))
+    }
+
+    /// Wave vector, with the index as its length, of an extraordinary ray going along `ray`.
+    pub fn wave(&self, ray: &Unit) -> Vector {
+        let axis = self.axis();
+        let along = axis.scale(ray.dot(&axis));
+        let wave = Unit::new_normalize(
+            (ray.into_inner() - along) * self.extraordinary_ior.powi(2) + along * self.ordinary_ior.powi(2),
+        );
+        wave.scale(self.index(&wave))
+    }
+
+    /// Extraordinary wave vector inside the crystal sharing the `tangential` component with the
+    /// incoming wave, `inward` being the surface normal pointing into the crystal.
+    pub fn extraordinary_wave(&self, tangential: &Vector, inward: &Unit) -> Option<Vector> {
+        let axis = self.axis();
+        let (ordinary_sq, extraordinary_sq) = (self.ordinary_ior.powi(2), self.extraordinary_ior.powi(2));
+        let (a, b) = (tangential.dot(&axis), inward.dot(&axis));
+
+        let qa = (1. - b * b) / extraordinary_sq + b * b / ordinary_sq;
+        let qb = 2. * a * b * (1. / ordinary_sq - 1. / extraordinary_sq);
+        let qc = (tangential.magnitude_squared() - a * a) / extraordinary_sq + a * a / ordinary_sq - 1.;
+
+        let discriminant = qb * qb - 4. * qa * qc;
+        if discriminant < 0. {
+            return None;
+        }
+        let normal_part = (-qb + discriminant.sqrt()) / (2. * qa);
+        (normal_part > 0.).then(|| tangential + inward.scale(normal_part))
+    }
+
+    /// Vibration axis of the ordinary wave travelling along `wave`, `None` along the optic axis
+    /// where both waves coincide.
+    pub fn ordinary_polarization(&self, wave: &Unit) -> Option<Vector> {
+        let axis = self.axis();
+        let cross = Vector3::new(wave.x, wave.y, wave.z).cross(&Vector3::new(axis.x, axis.y, axis.z));
+        (cross.norm() > 1e-6).then(|| cross.to_homogeneous())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::Birefringence;
+    use crate::renderer::objects::ray::{Ray, Unit, Vector};
+
+    fn calcite() -> Birefringence {
+        Birefringence {
+            ordinary_ior: 1.6584,
+            extraordinary_ior: 1.4864,
+            optic_axis: Vector::new(1., 0., 1., 0.),
+        }
+    }
+
+    #[test]
+    fn test_normal_incidence_walk_off() {
+        let normal = Unit::new_normalize(Vector::new(0., 0., 1., 0.));
+        let ray = Ray::new(Vector::zeros(), -normal, 1.);
+        let refractions = ray.refracted(&normal, 1.6584, Some(&calcite()));
+        assert_eq!(refractions.len(), 2);
+
+        let ordinary = &refractions[0];
+        assert_relative_eq!(ordinary.direction, -normal, epsilon = 1e-12);
+        assert_relative_eq!(ordinary.ior, 1.6584);
+
+        let extraordinary = &refractions[1];
+        assert!(extraordinary.direction.x.abs() > 0.05);
+        assert!(extraordinary.ior < 1.6584 && extraordinary.ior > 1.4864);
+    }
+
+    #[test]
+    fn test_slab_keeps_direction() {
+        let normal = Unit::new_normalize(Vector::new(0., 0.3, 1., 0.));
+        let direction = Unit::new_normalize(Vector::new(0.2, -0.4, -1., 0.));
+        let ray = Ray::new(Vector::zeros(), direction, 1.);
+
+        for refraction in ray.refracted(&normal, 1.6584, Some(&calcite())) {
+            let inside = refraction.ray(Vector::zeros());
+            let out = inside.refracted(&normal, 1., None);
+            assert_eq!(out.len(), 1);
+            assert_relative_eq!(out[0].direction, direction, epsilon = 1e-9);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use nalgebra::Complex;
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::ray::{RgbIntensity, RGB_WAVELENGTHS};
+
+/// A single thin film on top of the material, as in soap bubbles, oil slicks or
+/// anti-reflection lens coatings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThinFilm {
+    /// Film thickness in nanometers.
+    pub thickness: f64,
+    pub ior: f64,
+}
+
+impl ThinFilm {
+    /// Offsets in nm around each channel wavelength, averaged to avoid aliasing the fringes.
+    const BAND_OFFSETS: [f64; 4] = [-30., -10., 10., 30.];
+
+    /// Airy reflectance of the `outer | film | substrate` stack for unpolarized light.
+    /// `cos_incident` is measured in the outer medium.
+    pub fn reflectance(&self, cos_incident: f64, outer_ior: f64, substrate_ior: f64, wavelength: f64) -> f64 {
+        let [rs, rp] = self.amplitudes(cos_incident, outer_ior, substrate_ior, wavelength);
+        (rs.norm_sqr() + rp.norm_sqr()) / 2.
+    }
+
+    /// Complex reflection amplitudes for s and p polarization.
+    pub fn amplitudes(
+        &self,
+        cos_incident: f64,
+        outer_ior: f64,
+        substrate_ior: f64,
+        wavelength: f64,
+    ) -> [Complex<f64>; 2] {
+        let cos_1 = Complex::new(cos_incident.abs().min(1.), 0.);
+        let sin_sq_1 = 1. - cos_incident * cos_incident;
+        let cos_in = |ior: f64| Complex::new(1. - sin_sq_1 * (outer_ior / ior).powi(2), 0.).sqrt();
+        let wavenumber = 4. * std::f64::consts::PI * self.ior * self.thickness / wavelength;
+        let cos_2 = cos_in(self.ior);
+        let cos_3 = cos_in(substrate_ior);
+
+        let (n_1, n_2, n_3) = (outer_ior, self.ior, substrate_ior);
+        let shift = (Complex::<f64>::i() * cos_2 * wavenumber).exp();
+
+        let airy = |r_12: Complex<f64>, r_23: Complex<f64>| (r_12 + r_23 * shift) / (1. + r_12 * r_23 * shift);
+
+        let s = |n_i: f64, cos_i: Complex<f64>, n_j: f64, cos_j: Complex<f64>| {
+            (cos_i * n_i - cos_j * n_j) / (cos_i * n_i + cos_j * n_j)
+        };
+        let p = |n_i: f64, cos_i: Complex<f64>, n_j: f64, cos_j: Complex<f64>| {
+            (cos_i * n_j - cos_j * n_i) / (cos_i * n_j + cos_j * n_i)
+        };
+
+        [
+            airy(s(n_1, cos_1, n_2, cos_2), s(n_2, cos_2, n_3, cos_3)),
+            airy(p(n_1, cos_1, n_2, cos_2), p(n_2, cos_2, n_3, cos_3)),
+        ]
+    }
+
+    /// Reflectance per RGB channel, averaged over a band around each channel wavelength.
+    pub fn reflectance_rgb(&self, cos_incident: f64, outer_ior: f64, substrate_ior: f64) -> RgbIntensity {
+        RgbIntensity::from_fn(|channel, _| {
+            let total: f64 = Self::BAND_OFFSETS
+                .iter()
+                .map(|offset| {
+                    self.reflectance(cos_incident, outer_ior, substrate_ior, RGB_WAVELENGTHS[channel] + offset)
+                })
+                .sum();
+            (total / Self::BAND_OFFSETS.len() as f64) as f32
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::ThinFilm;
+
+    #[test]
+    fn test_zero_thickness_is_bare_fresnel() {
+        let film = ThinFilm { thickness: 0., ior: 1.38 };
+        assert_relative_eq!(film.reflectance(1., 1., 1.5, 550.), 0.04, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_quarter_wave_anti_reflection() {
+        let ior = 1.5f64.sqrt();
+        let film = ThinFilm { thickness: 550. / (4. * ior), ior };
+
+        assert_relative_eq!(film.reflectance(1., 1., 1.5, 550.), 0., epsilon = 1e-9);
+        assert!(film.reflectance(1., 1., 1.5, 450.) > 0.);
+    }
+
+    #[test]
+    fn test_total_internal_reflection() {
+        let film = ThinFilm { thickness: 300., ior: 1.33 };
+        assert_relative_eq!(film.reflectance(0.1, 1.5, 1., 550.), 1., epsilon = 1e-9);
+    }
+}
@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::ray::{Ray, Unit, Uv, Vector, Vector3};
+use crate::renderer::objects::texture::procedural::{Octaves, Placement};
+
+/// Index of refraction varying through the inside of an object, bending rays continuously
+/// as in mirages, heat shimmer or GRIN lenses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IorField {
+    /// `ior + gradient · (p - origin)`.
+    Linear {
+        ior: f64,
+        gradient: Vector3,
+        #[serde(default)]
+        origin: Vector3,
+    },
+    /// `ior · (1 - strength · r² / 2)`, `r` being the distance from the axis through `center`.
+    Radial {
+        ior: f64,
+        center: Vector3,
+        axis: Vector3,
+        strength: f64,
+    },
+    /// `ior + amplitude · fbm(p)`.
+    Noise {
+        ior: f64,
+        amplitude: f64,
+        #[serde(default, flatten)]
+        octaves: Octaves,
+        #[serde(default, flatten)]
+        placement: Placement,
+    },
+}
+
+/// Position and `n · dr/ds` of a ray inside the field.
+type State = (Vector3, Vector3);
+
+impl IorField {
+    const MIN_IOR: f64 = 1e-3;
+    const INITIAL_STEP: f64 = 0.01;
+    const MIN_STEP: f64 = 1e-5;
+    const MAX_STEP: f64 = 0.5;
+    const TOLERANCE: f64 = 1e-6;
+    const MAX_STEPS: usize = 100_000;
+    const DIFFERENCE: f64 = 1e-4;
+
+    pub fn ior(&self, pos: &Vector) -> f64 {
+        self.ior_at(&pos.xyz())
+    }
+
+    fn ior_at(&self, p: &Vector3) -> f64 {
+        let ior = match self {
+            IorField::Linear { ior, gradient, origin } => ior + gradient.dot(&(p - origin)),
+            IorField::Radial { ior, center, axis, strength } => {
+                ior * (1. - strength * Self::off_axis(p, center, axis).norm_squared() / 2.)
+            }
+            IorField::Noise { ior, amplitude, octaves, placement } => {
+                ior + amplitude * octaves.fbm(&placement.point(&Uv::zeros(), &p.to_homogeneous()))
+            }
+        };
+        ior.max(Self::MIN_IOR)
+    }
+
+    fn gradient_at(&self, p: &Vector3) -> Vector3 {
+        match self {
+            IorField::Linear { gradient, .. } => *gradient,
+            IorField::Radial { ior, center, axis, strength } => Self::off_axis(p, center, axis) * -(ior * strength),
+            IorField::Noise { .. } => Vector3::from_fn(|i, _| {
+                let offset = Vector3::ith(i, Self::DIFFERENCE);
+                (self.ior_at(&(p + offset)) - self.ior_at(&(p - offset))) / (2. * Self::DIFFERENCE)
+            }),
+        }
+    }
+
+    fn off_axis(p: &Vector3, center: &Vector3, axis: &Vector3) -> Vector3 {
+        let axis = axis.normalize();
+        let relative = p - center;
+        relative - axis * axis.dot(&relative)
+    }
+
+    /// Derivative of the ray equation `d/ds (n dr/ds) = ∇n` over the arc length `s`.
+    fn derivative(&self, (p, t): &State) -> State {
+        (t / self.ior_at(p), self.gradient_at(p))
+    }
+
+    fn rk4(&self, state: &State, h: f64) -> State {
+        let shifted = |(p, t): &State, (dp, dt): &State, by: f64| (p + dp * by, t + dt * by);
+
+        let k1 = self.derivative(state);
+        let k2 = self.derivative(&shifted(state, &k1, h / 2.));
+        let k3 = self.derivative(&shifted(state, &k2, h / 2.));
+        let k4 = self.derivative(&shifted(state, &k3, h));
+
+        (
+            state.0 + (k1.0 + k2.0 * 2. + k3.0 * 2. + k4.0) * (h / 6.),
+            state.1 + (k1.1 + k2.1 * 2. + k3.1 * 2. + k4.1) * (h / 6.),
+        )
+    }
+
+    /// Integrates `ray` through the field with adaptive RK4 steps until a straight step would
+    /// cross a surface; `boundary` gives the distance to the nearest surface along a ray.
+    /// Returns that last step as a straight ray carrying the local index.
+    pub fn trace(&self, ray: Ray, boundary: impl Fn(&Ray) -> Option<f64>) -> Ray {
+        let mut state: State = (ray.origin.xyz(), ray.direction.xyz() * self.ior(&ray.origin));
+        let mut step = Self::INITIAL_STEP;
+
+        for _ in 0..Self::MAX_STEPS {
+            let full = self.rk4(&state, step);
+            let half = self.rk4(&self.rk4(&state, step / 2.), step / 2.);
+            let error = (full.0 - half.0).norm();
+            if error > Self::TOLERANCE && step > Self::MIN_STEP {
+                step = (step / 2.).max(Self::MIN_STEP);
+                continue;
+            }
+
+            let chord = half.0 - state.0;
+            let segment = Ray::new(
+                state.0.to_homogeneous(),
+                Unit::new_normalize(chord.to_homogeneous()),
+                self.ior_at(&state.0),
+            );
+            if boundary(&segment).is_none_or(|distance| distance <= chord.norm()) {
+                return segment;
+            }
+
+            state = half;
+            if error < Self::TOLERANCE / 32. {
+                step = (step * 2.).min(Self::MAX_STEP);
+            }
+        }
+
+        Ray::new(state.0.to_homogeneous(), Unit::new_normalize(state.1.to_homogeneous()), self.ior_at(&state.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::IorField;
+    use crate::renderer::objects::ray::{Ray, Unit, Vector, Vector3};
+
+    #[test]
+    fn test_uniform_field_is_straight() {
+        let field = IorField::Linear { ior: 1.5, gradient: Vector3::zeros(), origin: Vector3::zeros() };
+        let direction = Unit::new_normalize(Vector::new(1., 2., 0.5, 0.));
+        let ray = Ray::new(Vector::zeros(), direction, 1.5);
+
+        let boundary = |segment: &Ray| Some(3. - (segment.origin - Vector::zeros()).magnitude());
+        let last = field.trace(ray, boundary);
+
+        assert_relative_eq!(last.direction, direction, epsilon = 1e-9);
+        assert_relative_eq!(last.ior, 1.5);
+    }
+
+    #[test]
+    fn test_rays_bend_towards_higher_index() {
+        let field = IorField::Linear { ior: 1.3, gradient: Vector3::new(0., 0., 0.05), origin: Vector3::zeros() };
+        let ray = Ray::new(Vector::zeros(), Unit::new_normalize(Vector::new(1., 0., 0., 0.)), 1.3);
+
+        let boundary = |segment: &Ray| Some(2. - segment.origin.x);
+        let last = field.trace(ray, boundary);
+
+        assert!(last.direction.z > 0.);
+        assert!(last.origin.z > 0.);
+    }
+}
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::ray::{RgbIntensity, Unit, Vector, Vector3, RGB_WAVELENGTHS};
+
+/// Layout of the grooves over the surface.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Grooves {
+    /// Parallel grooves along `direction`.
+    Straight { direction: Vector },
+    /// Concentric grooves around `center`, as on a CD.
+    Circular { center: Vector },
+}
+
+/// Periodic grooves on a reflective surface diffracting light into orders at angles that
+/// depend on the wavelength, as on a CD or in a spectrometer. Renderers trace the three
+/// `RGB_WAVELENGTHS` only, so white light splits into three colored lobes per order rather
+/// than a continuous rainbow.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffractionGrating {
+    /// Distance between neighbouring grooves in nanometers.
+    pub line_spacing: f64,
+    pub grooves: Grooves,
+    /// Highest order on each side of the mirror reflection.
+    #[serde(default = "DiffractionGrating::default_orders")]
+    pub orders: u32,
+    /// Fraction of the reflected light going into the orders other than zero, shared equally
+    /// by those that propagate.
+    #[serde(default = "DiffractionGrating::default_efficiency")]
+    pub efficiency: f32,
+}
+
+fn cross(a: &Vector, b: &Vector) -> Vector {
+    Vector3::new(a.x, a.y, a.z).cross(&Vector3::new(b.x, b.y, b.z)).to_homogeneous()
+}
+
+impl DiffractionGrating {
+    fn default_orders() -> u32 {
+        2
+    }
+
+    fn default_efficiency() -> f32 {
+        0.5
+    }
+
+    /// Unit vector in the surface across the grooves at `pos`, `None` where they have no
+    /// direction, as in the center of circular ones.
+    fn across(&self, pos: &Vector, normal: &Unit) -> Option<Unit> {
+        let along = match &self.grooves {
+            Grooves::Straight { direction } => *direction,
+            Grooves::Circular { center } => cross(normal, &(pos - center)),
+        };
+        Unit::try_new(cross(normal, &along), 1e-9)
+    }
+
+    /// Reflected directions of the orders other than zero that propagate for light of
+    /// `wavelength` in nm, by the grating equation on the tangential components.
+    pub fn orders(&self, direction: &Unit, normal: &Unit, pos: &Vector, wavelength: f64) -> Vec<Unit> {
+        let Some(across) = self.across(pos, normal) else {
+            return vec![];
+        };
+        let facing = if direction.dot(normal) > 0. { -*normal } else { *normal };
+        let tangential = direction.into_inner() - facing.scale(direction.dot(&facing));
+        let orders = self.orders as i32;
+
+        (-orders..=orders)
+            .filter(|&order| order != 0)
+            .filter_map(|order| {
+                let shifted = tangential + across.scale(order as f64 * wavelength / self.line_spacing);
+                let normal_sq = 1. - shifted.magnitude_squared();
+                (normal_sq > 0.).then(|| Unit::new_normalize(shifted + facing.scale(normal_sq.sqrt())))
+            })
+            .collect()
+    }
+
+    /// `orders` for each RGB channel.
+    pub fn channel_orders(&self, direction: &Unit, normal: &Unit, pos: &Vector) -> [Vec<Unit>; 3] {
+        RGB_WAVELENGTHS.map(|wavelength| self.orders(direction, normal, pos, wavelength))
+    }
+
+    /// Part of the reflected light left in the mirror direction; all of it in channels without
+    /// propagating orders.
+    pub fn mirror_share(&self, orders: &[Vec<Unit>; 3]) -> RgbIntensity {
+        RgbIntensity::from_fn(|channel, _| if orders[channel].is_empty() { 1. } else { 1. - self.efficiency })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::{DiffractionGrating, Grooves};
+    use crate::renderer::objects::ray::{Unit, Vector};
+
+    fn grating() -> DiffractionGrating {
+        DiffractionGrating {
+            line_spacing: 1000.,
+            grooves: Grooves::Straight { direction: Vector::new(0., 1., 0., 0.) },
+            orders: 2,
+            efficiency: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_grating_equation() {
+        let normal = Unit::new_normalize(Vector::new(0., 0., 1., 0.));
+        let orders = grating().orders(&-normal, &normal, &Vector::zeros(), 500.);
+
+        assert_eq!(orders.len(), 2);
+        for order in orders {
+            assert_relative_eq!(order.x.abs(), 0.5, epsilon = 1e-12);
+            assert!(order.z > 0.);
+        }
+    }
+
+    #[test]
+    fn test_red_spreads_wider_than_blue() {
+        let normal = Unit::new_normalize(Vector::new(0., 0., 1., 0.));
+        let direction = Unit::new_normalize(Vector::new(0.3, 0., -1., 0.));
+        let [red, _, blue] = grating().channel_orders(&direction, &normal, &Vector::zeros());
+
+        let spread = |orders: &Vec<Unit>| orders.iter().map(|o| o.x).fold(f64::MIN, f64::max);
+        assert!(spread(&red) > spread(&blue));
+    }
+}
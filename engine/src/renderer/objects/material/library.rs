@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use serde_yaml::{Mapping, Value};
+
+use crate::renderer::objects::material::birefringence::Birefringence;
+use crate::renderer::objects::material::{Material, MaterialBuilder, RgbIntensity};
+
+/// Named materials that scene files can reference instead of repeating every field.
+///
+/// A reference is either a name (`material: glass_bk7`) or a mapping with a `base` key whose
+/// remaining keys override the named material (`material: {base: water, color: [0.8, 0.9, 1.0]}`).
+#[derive(Debug, Clone)]
+pub struct MaterialLibrary {
+    entries: Mapping,
+}
+
+impl MaterialLibrary {
+    const BASE_KEY: &'static str = "base";
+    const MAX_DEPTH: usize = 32;
+
+    pub fn empty() -> Self {
+        MaterialLibrary { entries: Mapping::new() }
+    }
+
+    pub fn builtin() -> Self {
+        let mut library = Self::empty();
+        for (name, material) in Self::builtin_materials() {
+            library.entries.insert(
+                name.into(),
+                serde_yaml::to_value(material).expect("built-in materials serialize"),
+            );
+        }
+        library
+    }
+
+    /// Adds the entries of a scene `materials:` map. Entries may reference each other or
+    /// built-in materials through `base`.
+    pub fn extend(&mut self, materials: &Mapping) -> Result<(), Box<dyn Error>> {
+        let mut pending = self.entries.clone();
+        for (name, entry) in materials {
+            pending.insert(name.clone(), entry.clone());
+        }
+        let unresolved = MaterialLibrary { entries: pending };
+
+        for (name, _) in materials {
+            let name = name.as_str().ok_or("material names must be strings")?;
+            let resolved = unresolved.resolve_named(name, 0)?;
+            self.entries.insert(name.into(), resolved);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<Material, Box<dyn Error>> {
+        Ok(serde_yaml::from_value(self.resolve_named(name, 0)?)?)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().filter_map(Value::as_str)
+    }
+
+    /// Replaces a material reference with the full material mapping.
+    pub fn resolve(&self, reference: &Value) -> Result<Value, Box<dyn Error>> {
+        self.resolve_at(reference, 0)
+    }
+
+    fn resolve_named(&self, name: &str, depth: usize) -> Result<Value, Box<dyn Error>> {
+        if depth > Self::MAX_DEPTH {
+            return Err(format!("material {} references itself through `base`", name).into());
+        }
+        let entry = self.entries.get(name).ok_or_else(|| format!("unknown material {}", name))?;
+        self.resolve_at(entry, depth + 1)
+    }
+
+    fn resolve_at(&self, reference: &Value, depth: usize) -> Result<Value, Box<dyn Error>> {
+        match reference {
+            Value::String(name) => self.resolve_named(name, depth),
+            Value::Mapping(overrides) => match overrides.get(Self::BASE_KEY) {
+                None => Ok(reference.clone()),
+                Some(base) => {
+                    let base = base.as_str().ok_or("material `base` must be a name")?;
+                    let mut merged = self.resolve_named(base, depth)?;
+                    let mut overrides = overrides.clone();
+                    overrides.remove(Self::BASE_KEY);
+                    Self::merge(&mut merged, Value::Mapping(overrides));
+                    Ok(merged)
+                }
+            },
+            _ => Err("a material must be a name or a mapping".into()),
+        }
+    }
+
+    /// Deep merge of mappings; anything else is replaced.
+    fn merge(base: &mut Value, overrides: Value) {
+        match (base, overrides) {
+            (Value::Mapping(base), Value::Mapping(overrides)) => {
+                for (key, value) in overrides {
+                    match base.get_mut(&key) {
+                        Some(existing) => Self::merge(existing, value),
+                        None => {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (base, overrides) => *base = overrides,
+        }
+    }
+
+    fn dielectric(ior: f64, transmittance: RgbIntensity) -> Material {
+        MaterialBuilder::default()
+            .color([1.; 3].into())
+            .metallic([0.04; 3].into())
+            .roughness([0.; 3].into())
+            .k(200.)
+            .ior(ior)
+            .transmission(true)
+            .transmittance(transmittance)
+            .build()
+            .unwrap()
+    }
+
+    fn uniaxial(ordinary_ior: f64, extraordinary_ior: f64) -> Material {
+        Material {
+            birefringence: Some(Birefringence {
+                ordinary_ior,
+                extraordinary_ior,
+                optic_axis: [0., 0., 1., 0.].into(),
+            }),
+            ..Self::dielectric(ordinary_ior, [0.97; 3].into())
+        }
+    }
+
+    fn metal(reflectance: RgbIntensity) -> Material {
+        MaterialBuilder::default()
+            .color(reflectance)
+            .metallic([1.; 3].into())
+            .roughness([0.05; 3].into())
+            .k(100.)
+            .build()
+            .unwrap()
+    }
+
+    /// Refractive indices at the sodium D line (589 nm), metal colors are normal incidence
+    /// reflectance in linear RGB. Crystals have their optic axis along z.
+    fn builtin_materials() -> BTreeMap<&'static str, Material> {
+        BTreeMap::from([
+            ("metallic", Material::metallic()),
+            ("marble", Material::marble()),
+            ("vacuum", Self::dielectric(1., [1.; 3].into())),
+            ("air", Self::dielectric(1.000293, [1.; 3].into())),
+            ("water", Self::dielectric(1.333, [0.93, 0.97, 0.98].into())),
+            ("ice", Self::dielectric(1.309, [0.94, 0.97, 0.98].into())),
+            ("glass_bk7", Self::dielectric(1.5168, [0.96; 3].into())),
+            ("crown_glass", Self::dielectric(1.52, [0.96; 3].into())),
+            ("glass_f2", Self::dielectric(1.62, [0.95; 3].into())),
+            ("flint_glass", Self::dielectric(1.62, [0.95; 3].into())),
+            ("glass_sf11", Self::dielectric(1.7847, [0.94; 3].into())),
+            ("fused_silica", Self::dielectric(1.4585, [0.97; 3].into())),
+            ("acrylic", Self::dielectric(1.4905, [0.95; 3].into())),
+            ("polycarbonate", Self::dielectric(1.585, [0.94; 3].into())),
+            ("sapphire", Self::dielectric(1.768, [0.95; 3].into())),
+            ("diamond", Self::dielectric(2.417, [0.97; 3].into())),
+            ("calcite", Self::uniaxial(1.6584, 1.4864)),
+            ("quartz", Self::uniaxial(1.5443, 1.5534)),
+            ("gold", Self::metal([1., 0.766, 0.336].into())),
+            ("silver", Self::metal([0.972, 0.960, 0.915].into())),
+            ("copper", Self::metal([0.955, 0.637, 0.538].into())),
+            ("aluminium", Self::metal([0.913, 0.922, 0.924].into())),
+            ("iron", Self::metal([0.562, 0.565, 0.578].into())),
+            ("chromium", Self::metal([0.549, 0.556, 0.554].into())),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use serde_yaml::{Mapping, Value};
+
+    use super::MaterialLibrary;
+    use crate::renderer::objects::material::{Material, MaterialBuilder};
+
+    #[test]
+    fn test_override_on_named_base() {
+        let mut library = MaterialLibrary::builtin();
+        let materials: Mapping = serde_yaml::from_str(
+            "tinted_water: {base: water, color: [0.8, 0.9, 1.0]}\n\
+             deep_water: {base: tinted_water, transmittance: [0.5, 0.7, 0.9]}\n",
+        )
+        .unwrap();
+        library.extend(&materials).unwrap();
+
+        let deep = library.get("deep_water").unwrap();
+        assert_relative_eq!(deep.ior, 1.333);
+        assert_relative_eq!(deep.color, [0.8, 0.9, 1.0].into());
+        assert_relative_eq!(deep.transmittance, [0.5, 0.7, 0.9].into());
+
+        let reference = Value::String("glass_bk7".into());
+        let glass: super::Material = serde_yaml::from_value(library.resolve(&reference).unwrap()).unwrap();
+        assert!(glass.transmission);
+    }
+
+    #[test]
+    fn test_unknown_and_cyclic_references() {
+        let mut library = MaterialLibrary::builtin();
+        assert!(library.get("unobtainium").is_err());
+
+        let materials: Mapping = serde_yaml::from_str("a: {base: b}\nb: {base: a}\n").unwrap();
+        assert!(library.extend(&materials).is_err());
+    }
+
+    #[test]
+    fn test_minimal_yaml_matches_builder() {
+        let parsed: Material = serde_yaml::from_str("color: [0.5, 0.5, 0.5]").unwrap();
+        let built = MaterialBuilder::default().color([0.5; 3].into()).build().unwrap();
+        assert_eq!(serde_yaml::to_string(&parsed).unwrap(), serde_yaml::to_string(&built).unwrap());
+    }
+}
@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::ray::RgbIntensity;
+
+/// Volumetric scattering below the surface of a closed mesh, as in wax, milk, jade or skin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subsurface {
+    /// Fraction of light surviving each scattering event.
+    pub albedo: RgbIntensity,
+    /// Average distance between scattering events, in scene units.
+    pub mean_free_path: f64,
+    /// Henyey-Greenstein `g` of the scattering inside.
+    #[serde(default)]
+    pub anisotropy: f64,
+}
@@ -0,0 +1,263 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::phase::HenyeyGreenstein;
+use crate::renderer::objects::ray::{Ray, RgbIntensity, Vector, Vector3};
+
+/// Densities on a regular grid spanning the box `min..max`, interpolated trilinearly and
+/// zero outside of it. `values` go along x first, then y, then z.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DensityGrid {
+    pub min: Vector3,
+    pub max: Vector3,
+    pub resolution: [usize; 3],
+    pub values: Vec<f64>,
+}
+
+impl DensityGrid {
+    fn value(&self, x: usize, y: usize, z: usize) -> f64 {
+        let [nx, ny, _] = self.resolution;
+        self.values.get(x + nx * (y + ny * z)).copied().unwrap_or(0.)
+    }
+
+    pub fn density(&self, p: &Vector3) -> f64 {
+        let relative = (p - self.min).component_div(&(self.max - self.min));
+        if relative.iter().any(|c| !(0. ..=1.).contains(c)) {
+            return 0.;
+        }
+
+        let cell = Vector3::from_fn(|i, _| relative[i] * (self.resolution[i].max(1) - 1) as f64);
+        let base = cell.map(|c| c.floor());
+        let fraction = cell - base;
+        let corner = |i: usize, offset: usize| (base[i] as usize + offset).min(self.resolution[i].max(1) - 1);
+
+        let mut density = 0.;
+        for (dx, dy, dz) in (0..8).map(|i| (i & 1, (i >> 1) & 1, (i >> 2) & 1)) {
+            let weight = [dx, dy, dz]
+                .iter()
+                .enumerate()
+                .map(|(i, &d)| if d == 1 { fraction[i] } else { 1. - fraction[i] })
+                .product::<f64>();
+            density += weight * self.value(corner(0, dx), corner(1, dy), corner(2, dz));
+        }
+        density
+    }
+
+    pub fn max_density(&self) -> f64 {
+        self.values.iter().copied().fold(0., f64::max)
+    }
+
+    /// Part of the ray inside the grid box, as distances along it.
+    fn clip(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let (mut near, mut far) = (0., f64::INFINITY);
+        for i in 0..3 {
+            let (origin, direction) = (ray.origin[i], ray.direction[i]);
+            if direction.abs() < 1e-12 {
+                if origin < self.min[i] || origin > self.max[i] {
+                    return None;
+                }
+                continue;
+            }
+            let a = (self.min[i] - origin) / direction;
+            let b = (self.max[i] - origin) / direction;
+            near = f64::max(near, a.min(b));
+            far = f64::min(far, a.max(b));
+        }
+        (near < far).then_some((near, far))
+    }
+}
+
+/// What happened to a ray travelling through a medium.
+pub enum Collision {
+    /// Scattered at the point, with the throughput of getting there and scattering.
+    Scattered(Vector, RgbIntensity),
+    /// Reached the end of the segment, with the throughput of getting there.
+    Passed(RgbIntensity),
+    Absorbed,
+}
+
+/// Absorbing and scattering volume such as fog, smoke or murky water, filling the whole scene
+/// or the inside of a closed object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Medium {
+    /// Absorption coefficient per scene unit.
+    pub absorption: RgbIntensity,
+    /// Scattering coefficient per scene unit.
+    pub scattering: RgbIntensity,
+    /// Henyey-Greenstein `g`.
+    #[serde(default)]
+    pub anisotropy: f64,
+    /// Scales both coefficients through space; homogeneous when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub density: Option<DensityGrid>,
+}
+
+impl Medium {
+    /// Transmittance below which a segment going to infinity is cut off.
+    const CUTOFF: f64 = 1e-3;
+    const MARCH_STEPS: usize = 64;
+
+    pub fn homogeneous(absorption: RgbIntensity, scattering: RgbIntensity, anisotropy: f64) -> Self {
+        Medium { absorption, scattering, anisotropy, density: None }
+    }
+
+    pub fn phase(&self) -> HenyeyGreenstein {
+        HenyeyGreenstein::new(self.anisotropy)
+    }
+
+    pub fn extinction(&self) -> RgbIntensity {
+        self.absorption + self.scattering
+    }
+
+    pub fn density(&self, pos: &Vector) -> f64 {
+        self.density.as_ref().map_or(1., |grid| grid.density(&pos.xyz()))
+    }
+
+    fn majorant(&self) -> f64 {
+        self.extinction().max() as f64 * self.density.as_ref().map_or(1., DensityGrid::max_density)
+    }
+
+    /// Part of the first `distance` units of the ray where the medium has any effect.
+    fn span(&self, ray: &Ray, distance: f64) -> Option<(f64, f64)> {
+        let (start, end) = match &self.density {
+            Some(grid) => grid.clip(ray)?,
+            None => (0., f64::INFINITY),
+        };
+        let end = end.min(distance);
+        (self.majorant() > 0. && start < end).then_some((start, end))
+    }
+
+    /// Same as `span`, but cut where almost no light of the most dimmed channel gets through,
+    /// so finite even when `distance` is not.
+    pub fn range(&self, ray: &Ray, distance: f64) -> Option<(f64, f64)> {
+        let (start, end) = self.span(ray, distance)?;
+        let extinction = self.extinction().max() as f64;
+        let cutoff = if extinction > 0. { -Self::CUTOFF.ln() / extinction } else { f64::INFINITY };
+
+        let end = end.min(start + cutoff);
+        end.is_finite().then_some((start, end))
+    }
+
+    /// Fraction of light getting through the first `distance` units of the ray.
+    pub fn transmittance(&self, ray: &Ray, distance: f64) -> RgbIntensity {
+        if self.density.is_none() {
+            return self.extinction().map(|sigma| if sigma > 0. { (-sigma as f64 * distance).exp() as f32 } else { 1. });
+        }
+        let Some((start, end)) = self.span(ray, distance) else {
+            return [1.; 3].into();
+        };
+
+        let step = (end - start) / Self::MARCH_STEPS as f64;
+        let optical_depth: f64 = (0..Self::MARCH_STEPS)
+            .map(|i| self.density(&(ray.origin + ray.direction.scale(start + (i as f64 + 0.5) * step))) * step)
+            .sum();
+        self.extinction().map(|sigma| (-sigma as f64 * optical_depth).exp() as f32)
+    }
+
+    /// Unbiased estimate of `transmittance` by ratio tracking.
+    pub fn ratio_tracking(&self, ray: &Ray, distance: f64, rng: &mut impl Rng) -> RgbIntensity {
+        if self.density.is_none() {
+            return self.transmittance(ray, distance);
+        }
+        let Some((mut t, end)) = self.span(ray, distance) else {
+            return [1.; 3].into();
+        };
+
+        let majorant = self.majorant();
+        let mut transmittance = RgbIntensity::from([1.; 3]);
+        loop {
+            t -= (1. - rng.random::<f64>()).ln() / majorant;
+            if t >= end {
+                return transmittance;
+            }
+            let extinction = self.extinction() * self.density(&(ray.origin + ray.direction.scale(t))) as f32;
+            transmittance.component_mul_assign(&extinction.map(|sigma| 1. - sigma / majorant as f32));
+        }
+    }
+
+    /// Samples where the ray first interacts with the medium within `distance` units by delta
+    /// tracking, with the majorant covering all channels.
+    pub fn delta_tracking(&self, ray: &Ray, distance: f64, rng: &mut impl Rng) -> Collision {
+        let Some((mut t, end)) = self.span(ray, distance) else {
+            return Collision::Passed([1.; 3].into());
+        };
+
+        let majorant = self.majorant() as f32;
+        let mut weight = RgbIntensity::from([1.; 3]);
+        loop {
+            t -= (1. - rng.random::<f64>()).ln() / majorant as f64;
+            if t >= end {
+                return Collision::Passed(weight);
+            }
+
+            let pos = ray.origin + ray.direction.scale(t);
+            let density = self.density(&pos) as f32;
+            let (extinction, scattering) = (self.extinction() * density, self.scattering * density);
+            let scatter_chance = scattering.mean() / majorant;
+            let null_chance = 1. - extinction.mean() / majorant;
+
+            let choice = rng.random::<f32>();
+            if choice < scatter_chance {
+                return Collision::Scattered(pos, weight.component_mul(&scattering) / (majorant * scatter_chance));
+            } else if choice < scatter_chance + null_chance {
+                weight.component_mul_assign(&(RgbIntensity::from([majorant; 3]) - extinction));
+                weight /= majorant * null_chance;
+            } else {
+                return Collision::Absorbed;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use rand::SeedableRng;
+
+    use super::{DensityGrid, Medium};
+    use crate::renderer::objects::ray::{Ray, Unit, Vector};
+
+    fn smoke() -> Medium {
+        Medium {
+            density: Some(DensityGrid {
+                min: [0.; 3].into(),
+                max: [2.; 3].into(),
+                resolution: [2, 2, 2],
+                values: vec![0., 1., 0., 1., 0., 1., 0., 1.],
+            }),
+            ..Medium::homogeneous([0.2, 0.3, 0.4].into(), [0.5; 3].into(), 0.)
+        }
+    }
+
+    #[test]
+    fn test_grid_interpolation() {
+        let grid = smoke().density.unwrap();
+        assert_relative_eq!(grid.density(&[1., 1., 1.].into()), 0.5);
+        assert_relative_eq!(grid.density(&[2., 0., 0.].into()), 1.);
+        assert_relative_eq!(grid.density(&[3., 0., 0.].into()), 0.);
+    }
+
+    #[test]
+    fn test_ratio_tracking_matches_marching() {
+        let medium = smoke();
+        let ray = Ray::new(Vector::new(-1., 0.5, 0.5, 0.), Unit::new_normalize(Vector::new(1., 0.2, 0.1, 0.)), 1.);
+        let expected = medium.transmittance(&ray, 10.);
+
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        let n = 20_000;
+        let estimate = (0..n).map(|_| medium.ratio_tracking(&ray, 10., &mut rng)).sum::<super::RgbIntensity>() / n as f32;
+
+        assert_relative_eq!(estimate, expected, epsilon = 1e-2);
+    }
+
+    #[test]
+    fn test_range_with_clear_channel() {
+        let fog = Medium::homogeneous([0.; 3].into(), [0.1, 0.05, 0.].into(), 0.);
+        let ray = Ray::new(Vector::zeros(), Unit::new_normalize(Vector::new(1., 0., 0., 0.)), 1.);
+
+        let (start, end) = fog.range(&ray, f64::INFINITY).unwrap();
+        assert_eq!(start, 0.);
+        assert!(end.is_finite());
+        assert!(fog.transmittance(&ray, end).x < 1e-2);
+    }
+}
@@ -5,10 +5,33 @@ pub mod triangle;
 pub mod torus;
 
 use crate::renderer::objects::hit::Hit;
+use crate::renderer::objects::material::Material;
 use crate::renderer::objects::ray::{Ray, Vector};
 
+/// Hits closer than this along the ray are the surface the ray starts from.
+pub const MIN_FACTOR: f64 = 1e-7;
+
 pub trait Model {
-    fn hit(&self, ray: &Ray) -> Option<Hit>;
+    /// Closest hit of the ray with either side of the surface, unless the material culls back faces.
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>>;
+
+    fn material(&self) -> &Material;
+
+    /// Name that lights refer to the object by.
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    /// Total surface area, zero for models that can't be sampled.
+    fn area(&self) -> f64 {
+        0.
+    }
+
+    /// A point uniformly distributed over the surface for `u, v` in `[0, 1)`,
+    /// returned as a hit with zero `factor`.
+    fn sample(&self, _u: f64, _v: f64) -> Option<Hit<'_>> {
+        None
+    }
 }
 
 
@@ -3,14 +3,16 @@
 use serde::{Deserialize, Serialize};
 use crate::renderer::objects::hit::Hit;
 use crate::renderer::objects::material::Material;
-use crate::renderer::objects::model::Model;
-use crate::renderer::objects::ray::{Ray, Unit, Vector};
+use crate::renderer::objects::model::{Model, MIN_FACTOR};
+use crate::renderer::objects::ray::{Ray, Unit, Uv, Vector};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SphereModel {
     center: Vector,
     radius_sq: f64,
     material: Material,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
 }
 
 impl SphereModel {
@@ -19,12 +21,26 @@ impl SphereModel {
             center,
             radius_sq: radius * radius,
             material,
+            name: None,
         }
     }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Longitude/latitude mapping with the poles on the z axis.
+    fn spherical_uv(normal: &Unit) -> Uv {
+        Uv::new(
+            0.5 + normal.y.atan2(normal.x) / std::f64::consts::TAU,
+            1. - normal.z.clamp(-1., 1.).acos() / std::f64::consts::PI,
+        )
+    }
 }
 
 impl Model for SphereModel {
-    fn hit(&self, ray: &Ray) -> Option<Hit> {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
         let b = 2. * ray.direction.dot(&(ray.origin - self.center));
         let c = (self.center - ray.origin).magnitude_squared() - self.radius_sq;
 
@@ -32,18 +48,92 @@ impl Model for SphereModel {
         if d < 0. {
             None
         } else {
-            let t = (-b - d.sqrt()) / 2.;
-            if t < 0. {
+            let near = (-b - d.sqrt()) / 2.;
+            let far = (-b + d.sqrt()) / 2.;
+            let t = if near > MIN_FACTOR || self.material.backface_culling { near } else { far };
+            if t < MIN_FACTOR {
                 return None;
             }
 
             let hit_pos = ray.origin + ray.direction.scale(t);
+            let normal = Unit::new_normalize(hit_pos - self.center);
             Some(Hit::new(
                 t,
                 hit_pos,
                 &self.material,
-                Unit::new_normalize(hit_pos - self.center),
+                normal,
+                Self::spherical_uv(&normal),
+                Hit::orthogonalized(&Vector::new(-normal.y, normal.x, 0., 0.), &normal),
             ))
         }
     }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn area(&self) -> f64 {
+        4. * std::f64::consts::PI * self.radius_sq
+    }
+
+    fn sample(&self, u: f64, v: f64) -> Option<Hit<'_>> {
+        let z = 1. - 2. * u;
+        let r = (1. - z * z).max(0.).sqrt();
+        let phi = std::f64::consts::TAU * v;
+        let normal = Unit::new_unchecked(Vector::new(r * phi.cos(), r * phi.sin(), z, 0.));
+
+        Some(Hit::new(
+            0.,
+            self.center + normal.scale(self.radius_sq.sqrt()),
+            &self.material,
+            normal,
+            Self::spherical_uv(&normal),
+            Hit::orthogonalized(&Vector::new(-normal.y, normal.x, 0., 0.), &normal),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::renderer::objects::material::{Cutout, Material};
+    use crate::renderer::objects::model::sphere::SphereModel;
+    use crate::renderer::objects::ray::{Ray, Unit, Vector};
+    use crate::renderer::scene::Scene;
+
+    #[test]
+    fn test_hit_from_inside() {
+        let scene = Scene::new(vec![SphereModel::new(Vector::zeros(), 1., Material::default())]);
+        let direction = Unit::new_normalize(Vector::new(0., 0., 1., 0.));
+
+        let outside = scene.intersect(&Ray::new(Vector::new(0., 0., -2., 0.), direction, 1.)).unwrap();
+        assert!(outside.front_face);
+        assert_eq!(outside.pos, Vector::new(0., 0., -1., 0.));
+
+        let inside = scene.intersect(&Ray::new(Vector::zeros(), direction, 1.)).unwrap();
+        assert!(!inside.front_face);
+        assert_eq!(inside.pos, Vector::new(0., 0., 1., 0.));
+        assert_eq!(inside.normal, direction);
+
+        let culled = Material { backface_culling: true, ..Material::default() };
+        let scene = Scene::new(vec![SphereModel::new(Vector::zeros(), 1., culled)]);
+        assert!(scene.intersect(&Ray::new(Vector::zeros(), direction, 1.)).is_none());
+    }
+
+    #[test]
+    fn test_cutout_is_skipped() {
+        let leaf = Material { opacity: 0.3, cutout: Cutout::Threshold(0.5), ..Material::default() };
+        let scene = Scene::new(vec![
+            SphereModel::new(Vector::zeros(), 1., leaf),
+            SphereModel::new(Vector::new(0., 0., 5., 0.), 1., Material::default()),
+        ]);
+        let direction = Unit::new_normalize(Vector::new(0., 0., 1., 0.));
+
+        let hit = scene.intersect(&Ray::new(Vector::new(0., 0., -2., 0.), direction, 1.)).unwrap();
+        assert_eq!(hit.pos, Vector::new(0., 0., 4., 0.));
+        assert!((hit.factor - 6.).abs() < 1e-4);
+    }
 }
@@ -1,7 +1,7 @@
 use crate::renderer::objects::hit::Hit;
 use crate::renderer::objects::material::Material;
 use crate::renderer::objects::model::Model;
-use crate::renderer::objects::ray::{Ray, Unit, Vector};
+use crate::renderer::objects::ray::{Ray, Unit, Uv, Vector};
 
 pub struct TorusModel {
     pub r: f64,
@@ -10,7 +10,7 @@ pub struct TorusModel {
 }
 
 impl Model for TorusModel {
-    fn hit(&self, ray: &Ray) -> Option<Hit> {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
         let step = 0.05;
         let dir = ray.direction.normalize();
         for i in 0..60 {
@@ -20,14 +20,21 @@ impl Model for TorusModel {
             if (p.magnitude_squared() + self.r - self.k).powi(2)
                 < 4. * self.r * (p.x.powi(2) + p.y.powi(2))
             {
+                let normal = Unit::new_normalize(p - (p - Vector::new(0., 0., p.z, 0.)));
                 return Some(Hit::new(
                     t,
                     p,
                     &self.material,
-                    Unit::new_normalize(p - (p - Vector::new(0., 0., p.z, 0.))),
+                    normal,
+                    Uv::zeros(),
+                    Hit::default_tangent(&normal),
                 ));
             }
         }
         None
     }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
 }
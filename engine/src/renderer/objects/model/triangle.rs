@@ -2,12 +2,12 @@
 
 use crate::renderer::objects::hit::Hit;
 use crate::renderer::objects::material::Material;
-use crate::renderer::objects::model::Model;
-use crate::renderer::objects::ray::{Ray, Unit, Unit3, Vector, Vector3};
-use serde::{Deserialize, Serialize, Serializer};
+use crate::renderer::objects::model::{Model, MIN_FACTOR};
+use crate::renderer::objects::ray::{Ray, Unit, Vector, Vector3};
+use crate::renderer::objects::texture::UvProjection;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fs::OpenOptions;
-use std::path::Path;
 use std::sync::Arc as Rc;
 
 #[derive(Debug, Clone)]
@@ -52,6 +52,17 @@ impl Triangle {
     pub fn intersect(&self, ray: &Ray) -> f64 {
         (self.get_point(0) - ray.origin).dot(&self.normal) / ray.direction.dot(&self.normal)
     }
+
+    pub fn area(&self) -> f64 {
+        self.area
+    }
+
+    /// Uniform point on the triangle for `u, v` in `[0, 1)`.
+    pub fn sample(&self, u: f64, v: f64) -> Vector {
+        let su = u.sqrt();
+        let (b0, b1) = (1. - su, v * su);
+        self.get_point(0).scale(b0) + self.get_point(1).scale(b1) + self.get_point(2).scale(1. - b0 - b1)
+    }
 }
 
 #[derive(Debug, Clone,Default)]
@@ -101,6 +112,20 @@ pub struct TriangleModel {
     material: Material,
     #[serde(skip)]
     bounding_box: BoundingVolume,
+
+    #[serde(default)]
+    uv_projection: UvProjection,
+    #[serde(skip)]
+    uv_origin: Vector,
+    #[serde(skip)]
+    uv_size: f64,
+
+    /// Running total of triangle areas, for sampling the surface.
+    #[serde(skip)]
+    cumulative_area: Vec<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
 }
 
 impl TriangleModel {
@@ -112,9 +137,30 @@ impl TriangleModel {
             material,
             center: Vector::zeros(),
             bounding_box: BoundingVolume::default(),
+            uv_projection: UvProjection::default(),
+            uv_origin: Vector::zeros(),
+            uv_size: 1.,
+            cumulative_area: Vec::new(),
+            name: None,
         }
     }
 
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    fn surface_hit(&self, triangle: &Triangle, pos: Vector, factor: f64) -> Hit<'_> {
+        let uv = self.uv_projection.project(&((pos - self.uv_origin) / self.uv_size), &triangle.normal);
+        let tangent = self.uv_projection.tangent(&triangle.normal);
+        Hit::new(factor, pos, &self.material, triangle.normal, uv, tangent)
+    }
+
+    pub fn with_uv_projection(mut self, uv_projection: UvProjection) -> Self {
+        self.uv_projection = uv_projection;
+        self
+    }
+
     pub fn load_file(mut self) -> Result<Self, Box<dyn Error>> {
         let mut file = OpenOptions::new().read(true).open(&self.mesh_file)?;
         let stl = stl_io::read_stl(&mut file)?;
@@ -134,12 +180,12 @@ impl TriangleModel {
 
                 Some(Triangle::new(
                     norm,
-                    face.vertices.clone(),
+                    face.vertices,
                     self.points.clone()
                     ),
                 )
             })
-            .collect::<Vec<_>>().into();
+            .collect::<Vec<_>>();
 
         let mut bounds = [[0., 0.], [0., 0.], [0., 0.]]; // x, y, z [min, max]
         self.points.iter().for_each(|point| {
@@ -156,12 +202,22 @@ impl TriangleModel {
         self.center = Vector::new((bounds[0][1] + bounds[0][0]) / 2., (bounds[1][1] + bounds[1][0]) / 2.0, (bounds[2][1] + bounds[2][0]) / 2., 0.);
         self.bounding_box = BoundingVolume::new(self.center, (Vector::new(bounds[0][1],bounds[1][1], bounds[2][1], 0.) - self.center).magnitude());
 
+        self.uv_origin = Vector::new(bounds[0][0], bounds[1][0], bounds[2][0], 0.);
+        self.uv_size = bounds.iter().map(|[min, max]| max - min).fold(f64::EPSILON, f64::max);
+
+        self.cumulative_area = self.triangles.iter()
+            .scan(0., |total, triangle| {
+                *total += triangle.area();
+                Some(*total)
+            })
+            .collect();
+
         Ok(self)
     }
 }
 
 impl Model for TriangleModel {
-    fn hit(&self, ray: &Ray) -> Option<Hit> {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
         if ! self.bounding_box.hit(ray) {
             return None; // todo темный потому что логика пересечения не та
         }
@@ -172,13 +228,13 @@ impl Model for TriangleModel {
         (*self.triangles)
             .iter()
             .for_each(|triangle| {
-                if -triangle.normal.dot(&ray.direction) < 0. {
+                if self.material.backface_culling && triangle.normal.dot(&ray.direction) > 0. {
                     return;
                 }
 
                 let t = triangle.intersect(ray);
 
-                if t < 0. || min_t <= t {
+                if t < MIN_FACTOR || min_t <= t {
                     return;
                 }
 
@@ -186,10 +242,61 @@ impl Model for TriangleModel {
 
                 if triangle.point_in(&hit_pos) {
                     min_t = t;
-                    min_hit = Some(Hit::new(t, hit_pos, &self.material, triangle.normal));
+                    min_hit = Some(self.surface_hit(triangle, hit_pos, t));
                 }
             });
 
         min_hit
     }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn area(&self) -> f64 {
+        self.cumulative_area.last().copied().unwrap_or(0.)
+    }
+
+    fn sample(&self, u: f64, v: f64) -> Option<Hit<'_>> {
+        let target = u * self.area();
+        let index = self.cumulative_area
+            .partition_point(|&total| total <= target)
+            .min(self.triangles.len().checked_sub(1)?);
+
+        let triangle = &self.triangles[index];
+        let before = self.cumulative_area[index] - triangle.area();
+        let u = ((target - before) / triangle.area()).clamp(0., 1.);
+
+        Some(self.surface_hit(triangle, triangle.sample(u, v), 0.))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TriangleModel;
+    use crate::renderer::objects::material::MaterialBuilder;
+    use crate::renderer::objects::ray::{Ray, Unit, Vector};
+    use crate::renderer::scene::Scene;
+
+    #[test]
+    fn test_refracted_ray_leaves_glass() {
+        let glass = MaterialBuilder::default().transmission(true).ior(1.5).build().unwrap();
+        let scene = Scene::new(vec![TriangleModel::new("../test_data/Glass.stl".into(), glass).load_file().unwrap()]);
+        let ray = Ray::new(Vector::new(5., -0.4, 0.5, 0.), Unit::new_normalize(Vector::new(-1., 0.05, 0.1, 0.)), 1.);
+
+        let entry = scene.intersect(&ray).unwrap();
+        assert!(entry.front_face);
+        let direction = ray.refracted_dir(&entry.normal, 1.5).unwrap();
+        let inside = Ray::new(entry.pos + direction.scale(1e-6), direction, 1.5);
+
+        let exit = scene.intersect(&inside).unwrap();
+        assert!(!exit.front_face);
+        assert!(exit.geometric_normal.dot(&direction) > 0.);
+        assert!(exit.shading_normal().dot(&direction) < 0.);
+        assert!(inside.refracted_dir(&exit.normal, 1.).is_some_and(|out| out.dot(&exit.geometric_normal) > 0.));
+    }
 }
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::hit::Hit;
+use crate::renderer::objects::ray::{Unit, Vector3};
+
+/// Henyey-Greenstein phase function; `g > 0` scatters forward, `g < 0` backward.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct HenyeyGreenstein {
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        HenyeyGreenstein { g: g.clamp(-0.999, 0.999) }
+    }
+
+    /// Density per steradian of scattering by an angle with cosine `cos`.
+    pub fn evaluate(&self, cos: f64) -> f64 {
+        let g = self.g;
+        let denominator = 1. + g * g - 2. * g * cos;
+        (1. - g * g) / (4. * std::f64::consts::PI * denominator * denominator.sqrt())
+    }
+
+    /// Cosine between the incoming and the scattered direction for `u` in `[0, 1)`.
+    pub fn sample_cos(&self, u: f64) -> f64 {
+        let g = self.g;
+        if g.abs() < 1e-3 {
+            return 1. - 2. * u;
+        }
+        let s = (1. - g * g) / (1. - g + 2. * g * u);
+        ((1. + g * g - s * s) / (2. * g)).clamp(-1., 1.)
+    }
+
+    /// A scattered direction for a ray travelling along `direction`.
+    pub fn sample(&self, direction: &Unit, u1: f64, u2: f64) -> Unit {
+        let cos = self.sample_cos(u1);
+        let sin = (1. - cos * cos).max(0.).sqrt();
+        let phi = std::f64::consts::TAU * u2;
+
+        let t1 = Hit::default_tangent(direction);
+        let d3 = Vector3::new(direction.x, direction.y, direction.z);
+        let t2 = d3.cross(&Vector3::new(t1.x, t1.y, t1.z)).to_homogeneous();
+
+        Unit::new_normalize(
+            direction.scale(cos) + t1.scale(sin * phi.cos()) + t2.scale(sin * phi.sin()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::HenyeyGreenstein;
+
+    #[test]
+    fn test_mean_cosine_is_anisotropy() {
+        for g in [-0.6, 0., 0.3, 0.9] {
+            let phase = HenyeyGreenstein::new(g);
+            let n = 100_000;
+            let mean = (0..n).map(|i| phase.sample_cos((i as f64 + 0.5) / n as f64)).sum::<f64>() / n as f64;
+            assert_relative_eq!(mean, g, epsilon = 1e-3);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use nalgebra::{Complex, Matrix3, Matrix4, Matrix4x3};
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::ray::{RgbIntensity, Unit, Vector, Vector3};
+
+/// Stokes vectors `(I, Q, U, V)` of the red, green and blue channels, one per column.
+/// `Q` is positive along the reference axis of the frame the vectors are expressed in.
+pub type StokesRgb = Matrix4x3<f64>;
+
+pub type Mueller = Matrix4<f64>;
+
+/// Polarization of light: its Stokes vectors and the reference axis of their frame,
+/// orthogonal to the direction the light goes.
+#[derive(Debug, Clone)]
+pub struct Polarization {
+    pub frame: Unit,
+    pub stokes: StokesRgb,
+}
+
+impl Polarization {
+    pub fn unpolarized(frame: Unit, intensity: &RgbIntensity) -> Self {
+        Polarization { frame, stokes: unpolarized(intensity) }
+    }
+
+    /// Applies `mueller`, given in the frame with reference axis `frame`, to light going along
+    /// `propagation`; the Stokes vectors are turned to that frame first and stay in it.
+    pub fn transformed(&self, mueller: &Mueller, frame: Unit, propagation: &Unit) -> Self {
+        Polarization { frame, stokes: mueller * rotation_between(&self.frame, &frame, propagation) * self.stokes }
+    }
+}
+
+pub fn unpolarized(intensity: &RgbIntensity) -> StokesRgb {
+    let mut stokes = StokesRgb::zeros();
+    for channel in 0..3 {
+        stokes[(0, channel)] = intensity[channel] as f64;
+    }
+    stokes
+}
+
+pub fn intensity(stokes: &StokesRgb) -> RgbIntensity {
+    RgbIntensity::from_fn(|channel, _| stokes[(0, channel)] as f32)
+}
+
+pub fn degree_of_polarization(stokes: &StokesRgb) -> RgbIntensity {
+    RgbIntensity::from_fn(|channel, _| {
+        let column = stokes.column(channel);
+        if column[0] <= 0. {
+            0.
+        } else {
+            (column.fixed_rows::<3>(1).norm() / column[0]).min(1.) as f32
+        }
+    })
+}
+
+pub fn scaled(stokes: &StokesRgb, weights: &RgbIntensity) -> StokesRgb {
+    stokes * Matrix3::from_diagonal(&weights.map(|w| w as f64))
+}
+
+fn cross(a: &Vector, b: &Vector) -> Vector {
+    Vector3::new(a.x, a.y, a.z).cross(&Vector3::new(b.x, b.y, b.z)).to_homogeneous()
+}
+
+/// The s axis of a ray hitting a surface, perpendicular to the plane of incidence.
+/// `None` at normal incidence, where any axis will do.
+pub fn s_axis(normal: &Unit, direction: &Unit) -> Option<Unit> {
+    Unit::try_new(cross(normal, direction), 1e-9)
+}
+
+/// Applies a different Mueller matrix to each channel.
+pub fn per_channel(muellers: &[Mueller; 3], stokes: &StokesRgb) -> StokesRgb {
+    let mut result = StokesRgb::zeros();
+    for (channel, mueller) in muellers.iter().enumerate() {
+        result.set_column(channel, &(mueller * stokes.column(channel)));
+    }
+    result
+}
+
+/// Stokes rotation for a reference axis turned by `angle` around the propagation direction.
+pub fn rotation(angle: f64) -> Mueller {
+    let (sin, cos) = (2. * angle).sin_cos();
+    Mueller::new(
+        1., 0., 0., 0.,
+        0., cos, sin, 0.,
+        0., -sin, cos, 0.,
+        0., 0., 0., 1.,
+    )
+}
+
+/// Re-expresses Stokes vectors from reference axis `from` to reference axis `to`,
+/// both orthogonal to `propagation`.
+pub fn rotation_between(from: &Unit, to: &Unit, propagation: &Unit) -> Mueller {
+    rotation(propagation.dot(&cross(from, to)).atan2(from.dot(to)))
+}
+
+/// Mueller matrix of an interface with complex amplitude coefficients `s` and `p`,
+/// with `s` as the reference axis.
+pub fn from_amplitudes(s: Complex<f64>, p: Complex<f64>) -> Mueller {
+    let (ss, pp) = (s.norm_sqr(), p.norm_sqr());
+    let cross = s * p.conj();
+    0.5 * Mueller::new(
+        ss + pp, ss - pp, 0., 0.,
+        ss - pp, ss + pp, 0., 0.,
+        0., 0., 2. * cross.re, 2. * cross.im,
+        0., 0., -2. * cross.im, 2. * cross.re,
+    )
+}
+
+fn cos_transmitted(cos_incident: f64, ior: f64, next_ior: f64) -> Complex<f64> {
+    let sin_sq = (1. - cos_incident * cos_incident) * (ior / next_ior).powi(2);
+    Complex::new(1. - sin_sq, 0.).sqrt()
+}
+
+/// Fresnel reflection going from `ior` into `next_ior`.
+pub fn fresnel_reflection(cos_incident: f64, ior: f64, next_ior: f64) -> Mueller {
+    let cos_i = Complex::new(cos_incident.abs(), 0.);
+    let cos_t = cos_transmitted(cos_incident, ior, next_ior);
+    let s = (cos_i * ior - cos_t * next_ior) / (cos_i * ior + cos_t * next_ior);
+    let p = (cos_i * next_ior - cos_t * ior) / (cos_i * next_ior + cos_t * ior);
+    from_amplitudes(s, p)
+}
+
+/// Fresnel transmission going from `ior` into `next_ior`, zero past the critical angle.
+pub fn fresnel_transmission(cos_incident: f64, ior: f64, next_ior: f64) -> Mueller {
+    let cos_i = cos_incident.abs();
+    let cos_t = cos_transmitted(cos_incident, ior, next_ior);
+    if cos_t.im != 0. {
+        return Mueller::zeros();
+    }
+    let cos_t = cos_t.re;
+    let s = 2. * ior * cos_i / (ior * cos_i + next_ior * cos_t);
+    let p = 2. * ior * cos_i / (next_ior * cos_i + ior * cos_t);
+    let beam = (next_ior * cos_t / (ior * cos_i)).sqrt();
+    from_amplitudes(Complex::new(s * beam, 0.), Complex::new(p * beam, 0.))
+}
+
+/// Fresnel reflection `mueller` rescaled for a surface reflecting `weight` of unpolarized
+/// light head on, where the interface reflects `head_on`; like the interface, it reflects all
+/// of it at grazing angles. Light polarized in the plane of incidence still goes dark at
+/// Brewster's angle.
+pub fn reflection_weighted(mueller: Mueller, head_on: f64, weight: f64) -> Mueller {
+    let reflected = mueller[(0, 0)];
+    let scale = if head_on < 1. { weight + (1. - weight) * (reflected - head_on) / (1. - head_on) } else { weight };
+    let shape = if reflected > f64::EPSILON { mueller / reflected } else { Mueller::identity() };
+    shape * scale.clamp(0., 1.)
+}
+
+/// Fresnel transmission `mueller` relative to the transmission `head_on` of the interface at
+/// normal incidence, for surfaces whose transmittance is given head on.
+pub fn transmission_weighted(mueller: Mueller, head_on: f64) -> Mueller {
+    if head_on > f64::EPSILON { mueller / head_on } else { Mueller::identity() }
+}
+
+/// An ideal linear polarizer passing light polarized along `axis`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinearPolarizer {
+    pub axis: Vector,
+}
+
+impl LinearPolarizer {
+    /// Mueller matrix for light along `propagation`, in the frame with reference axis `reference`.
+    pub fn mueller(&self, reference: &Unit, propagation: &Unit) -> Mueller {
+        let axis = self.axis - propagation.scale(propagation.dot(&self.axis));
+        let Some(axis) = Unit::try_new(axis, 1e-9) else {
+            return Mueller::zeros();
+        };
+        let angle = propagation.dot(&cross(reference, &axis)).atan2(reference.dot(&axis));
+        let (sin, cos) = (2. * angle).sin_cos();
+        0.5 * Mueller::new(
+            1., cos, sin, 0.,
+            cos, cos * cos, cos * sin, 0.,
+            sin, cos * sin, sin * sin, 0.,
+            0., 0., 0., 0.,
+        )
+    }
+}
+
+/// What a polarization-aware renderer writes to the image.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolarizationOutput {
+    Intensity,
+    DegreeOfPolarization,
+    /// Intensity seen through a polarizing filter in front of the camera.
+    Filtered(LinearPolarizer),
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::{
+        fresnel_reflection, fresnel_transmission, reflection_weighted, rotation_between, LinearPolarizer, Mueller,
+        Polarization,
+    };
+    use crate::renderer::objects::ray::{Unit, Vector};
+
+    #[test]
+    fn test_brewster_reflection_is_s_polarized() {
+        let ior = 1.5f64;
+        let brewster = ior.atan();
+        let reflection = fresnel_reflection(brewster.cos(), 1., ior);
+        let unpolarized = nalgebra::Vector4::new(1., 0., 0., 0.);
+        let reflected = reflection * unpolarized;
+
+        assert!(reflected[0] > 0.);
+        assert_relative_eq!(reflected[1], reflected[0], epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_weighted_reflection_dims_at_brewster() {
+        let ior = 1.5f64;
+        let head_on = fresnel_reflection(1., 1., ior)[(0, 0)];
+        let weighted = |cos: f64| reflection_weighted(fresnel_reflection(cos, 1., ior), head_on, 0.5);
+        let p_polarized = nalgebra::Vector4::new(1., -1., 0., 0.);
+
+        assert_relative_eq!((weighted(1.) * p_polarized)[0], 0.5, epsilon = 1e-12);
+        assert_relative_eq!((weighted(ior.atan().cos()) * p_polarized)[0], 0., epsilon = 1e-12);
+        assert_relative_eq!(weighted(1e-9)[(0, 0)], 1., epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_fresnel_energy_conservation() {
+        for cos in [1., 0.8, 0.3, 0.05] {
+            let total: Mueller = fresnel_reflection(cos, 1., 1.33) + fresnel_transmission(cos, 1., 1.33);
+            assert_relative_eq!(total[(0, 0)], 1., epsilon = 1e-12);
+            assert_relative_eq!(total[(1, 1)], 1., epsilon = 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_crossed_polarizers_block_light() {
+        let propagation = Unit::new_normalize(Vector::new(0., 0., 1., 0.));
+        let reference = Unit::new_normalize(Vector::new(1., 0., 0., 0.));
+        let first = LinearPolarizer { axis: Vector::new(1., 1., 0., 0.) };
+        let crossed = LinearPolarizer { axis: Vector::new(-1., 1., 0., 0.) };
+        let unpolarized = nalgebra::Vector4::new(1., 0., 0., 0.);
+
+        let through_first = first.mueller(&reference, &propagation) * unpolarized;
+        assert_relative_eq!(through_first[0], 0.5, epsilon = 1e-12);
+
+        let other_frame = Unit::new_normalize(Vector::new(0., 1., 0., 0.));
+        let rotated = rotation_between(&reference, &other_frame, &propagation) * through_first;
+        let blocked = crossed.mueller(&other_frame, &propagation) * rotated;
+        assert_relative_eq!(blocked[0], 0., epsilon = 1e-12);
+
+        let light = Polarization::unpolarized(reference, &[1.; 3].into())
+            .transformed(&first.mueller(&other_frame, &propagation), other_frame, &propagation)
+            .transformed(&crossed.mueller(&reference, &propagation), reference, &propagation);
+        assert_relative_eq!(light.stokes.row(0).sum(), 0., epsilon = 1e-12);
+    }
+}
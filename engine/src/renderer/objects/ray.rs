@@ -1,7 +1,8 @@
-use std::convert::Into;
-use nalgebra::{Matrix4, Vector4};
+use nalgebra::{Matrix4, Vector2, Vector4};
 use nalgebra::Vector3 as V3;
 use nalgebra::Unit as U;
+use crate::renderer::objects::hit::Hit;
+use crate::renderer::objects::material::birefringence::Birefringence;
 
 pub type Vector = Vector4<f64>;
 pub type Vector3 = V3<f64>;
@@ -10,8 +11,13 @@ pub type Unit3 = U<Vector3>;
 
 pub type Matrix = Matrix4<f64>;
 
+pub type Uv = Vector2<f64>;
+
 pub type RgbIntensity = V3<f32>;
 
+/// Representative wavelengths in nm of the red, green and blue channels.
+pub const RGB_WAVELENGTHS: [f64; 3] = [610., 550., 465.];
+
 pub struct Rgb(pub RgbIntensity);
 impl Rgb {
 
@@ -41,19 +47,88 @@ impl Rgb {
 pub struct Ray {
     pub origin: Vector,
     pub direction: Unit,
-    pub ior: f64
+    pub ior: f64,
+    /// Set for extraordinary rays inside a birefringent crystal, whose wave vector is not
+    /// along `direction`.
+    pub extraordinary: Option<Birefringence>,
+}
+
+/// One of the rays a refraction produces.
+#[derive(Debug, Clone)]
+pub struct Refraction {
+    pub direction: Unit,
+    pub ior: f64,
+    pub extraordinary: Option<Birefringence>,
+    /// Vibration axis of the light taking this ray when a birefringent crystal split it.
+    pub polarization: Option<Vector>,
+}
+
+impl Refraction {
+    /// Share of unpolarized light taking this ray.
+    pub fn weight(&self) -> f64 {
+        if self.polarization.is_some() { 0.5 } else { 1. }
+    }
+
+    pub fn ray(&self, origin: Vector) -> Ray {
+        Ray { extraordinary: self.extraordinary, ..Ray::new(origin, self.direction, self.ior) }
+    }
 }
 
 impl Ray {
 
     pub fn new(origin: Vector, direction: Unit, env: f64) -> Ray {
-        Ray { origin, direction, ior: env }
+        Ray { origin, direction, ior: env, extraordinary: None }
+    }
+
+    /// Wave vector with the index of the medium as its length.
+    fn wave(&self) -> Vector {
+        match &self.extraordinary {
+            Some(crystal) => crystal.wave(&self.direction),
+            None => self.direction.scale(self.ior),
+        }
+    }
+
+    /// Refraction into a medium of index `env_nu`, or into a birefringent `crystal`, where the ray
+    /// splits into the ordinary and the extraordinary ray. Empty on total internal reflection.
+    pub fn refracted(&self, normal: &Unit, env_nu: f64, crystal: Option<&Birefringence>) -> Vec<Refraction> {
+        let inward = if self.direction.dot(normal) >= 0. { *normal } else { -*normal };
+        let wave = self.wave();
+        let tangential = wave - inward.scale(wave.dot(&inward));
+
+        let ordinary_ior = crystal.map_or(env_nu, |crystal| crystal.ordinary_ior);
+        let normal_sq = ordinary_ior * ordinary_ior - tangential.magnitude_squared();
+        let ordinary_direction = Unit::new_normalize(tangential + inward.scale(normal_sq.max(0.).sqrt()));
+        let ordinary = (normal_sq >= 0.).then_some(Refraction {
+            direction: ordinary_direction,
+            ior: ordinary_ior,
+            extraordinary: None,
+            polarization: None,
+        });
+
+        let Some(crystal) = crystal else {
+            return ordinary.into_iter().collect();
+        };
+        let Some(ordinary_polarization) = crystal.ordinary_polarization(&ordinary_direction) else {
+            return ordinary.into_iter().collect();
+        };
+
+        let extraordinary = crystal.extraordinary_wave(&tangential, &inward).map(|wave| Refraction {
+            direction: crystal.ray_direction(&wave),
+            ior: wave.magnitude(),
+            extraordinary: Some(*crystal),
+            polarization: Some(crystal.optic_axis),
+        });
+        ordinary
+            .map(|ordinary| Refraction { polarization: Some(ordinary_polarization), ..ordinary })
+            .into_iter()
+            .chain(extraordinary)
+            .collect()
     }
 
     pub fn refracted_dir(&self, normal: &Unit, env_nu: f64) -> Option<Unit>
     {
         let dot_prod = self.direction.dot(normal);
-        let norm = if dot_prod >= 0. {normal.scale(-1.)} else { *normal.clone() };
+        let norm = if dot_prod >= 0. {normal.scale(-1.)} else { **normal };
 
         let r = self.ior / env_nu;
         let c = -self.direction.dot(&norm);
@@ -70,6 +145,11 @@ impl Ray {
         }
     }
 
+    /// Reference axis of the Stokes frame for light travelling along this ray, either way.
+    pub fn polarization_frame(&self) -> Unit {
+        Hit::default_tangent(&self.direction)
+    }
+
     pub fn reflected_dir(&self, normal: &Unit) -> Unit
     {
         Unit::new_unchecked(
@@ -83,7 +163,7 @@ impl Ray {
 #[cfg(test)]
 mod tests {
     use approx::assert_relative_eq;
-    use crate::renderer::objects::ray::{Ray, Vector, Unit};
+    use crate::renderer::objects::ray::{Ray, Unit};
 
     #[test]
     fn test_refracted_direction() {
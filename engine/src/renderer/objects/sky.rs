@@ -0,0 +1,249 @@
+use std::f64::consts::{FRAC_PI_2, PI, TAU};
+
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::light::DirectionalLight;
+use crate::renderer::objects::ray::{RgbIntensity, Unit, Vector};
+
+/// Illuminance of the sun above the atmosphere in klx, matching sky luminances in kcd/m².
+const SUN_ILLUMINANCE: f32 = 128.;
+const SUN_ANGULAR_DIAMETER: f64 = 0.0093;
+
+/// Perez et al. luminance distribution, relative to the zenith.
+#[derive(Debug, Clone, Copy)]
+struct Perez([f64; 5]);
+
+impl Perez {
+    fn new(turbidity: f64, coefficients: [[f64; 2]; 5]) -> Self {
+        Perez(coefficients.map(|[slope, offset]| slope * turbidity + offset))
+    }
+
+    /// Distribution at a view `cos_theta` from the zenith and `gamma` from the sun.
+    fn value(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.0;
+        (1. + a * (b / cos_theta.max(0.01)).exp()) * (1. + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    }
+}
+
+/// Fraction of sunlight at `wavelength` in µm left after `mass` atmospheres of Rayleigh and
+/// aerosol scattering.
+fn transmittance(wavelength: f64, mass: f64, turbidity: f64) -> f64 {
+    let beta = 0.04608365822050 * turbidity - 0.04586025928522;
+    (-0.008735 * wavelength.powf(-4.08) * mass).exp() * (-beta * wavelength.powf(-1.3) * mass).exp()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PhysicalSkyConfig {
+    /// Angle of the sun above the horizon in radians.
+    sun_elevation: f64,
+    /// Angle of the sun around the z axis from x towards y in radians.
+    #[serde(default)]
+    sun_azimuth: f64,
+    #[serde(default = "PhysicalSkyConfig::default_turbidity")]
+    turbidity: f64,
+    #[serde(default = "PhysicalSkyConfig::default_ground_albedo")]
+    ground_albedo: RgbIntensity,
+    #[serde(default = "PhysicalSkyConfig::default_intensity")]
+    intensity: f32,
+    /// Adds the light from the sun to the scene.
+    #[serde(default)]
+    sun: bool,
+}
+
+impl PhysicalSkyConfig {
+    fn default_turbidity() -> f64 {
+        3.
+    }
+
+    fn default_ground_albedo() -> RgbIntensity {
+        [0.2; 3].into()
+    }
+
+    fn default_intensity() -> f32 {
+        0.1
+    }
+}
+
+/// Clear daylight sky of Preetham et al., z being up. Turbidity from 2, very clear, to 10,
+/// hazy. Below the horizon a diffuse ground of `ground_albedo` reflects the sky and the sun.
+/// `intensity` scales the luminances from kcd/m²; pair it with the light from `sun`, which
+/// scene files do with `sun: true`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "PhysicalSkyConfig", into = "PhysicalSkyConfig")]
+pub struct PhysicalSky {
+    sun_elevation: f64,
+    sun_azimuth: f64,
+    turbidity: f64,
+    ground_albedo: RgbIntensity,
+    intensity: f32,
+    /// Towards the sun.
+    sun: Unit,
+    /// Distributions and zenith values of luminance and of the x, y chromaticities.
+    perez: [Perez; 3],
+    zenith: [f64; 3],
+    ground: RgbIntensity,
+    /// Whether scenes take the light from `sun` along with the sky.
+    sun_light: bool,
+}
+
+impl PhysicalSky {
+    pub fn new(sun_elevation: f64, sun_azimuth: f64, turbidity: f64, ground_albedo: RgbIntensity, intensity: f32) -> Self {
+        let sun = Unit::new_normalize(Vector::new(
+            sun_elevation.cos() * sun_azimuth.cos(),
+            sun_elevation.cos() * sun_azimuth.sin(),
+            sun_elevation.sin(),
+            0.,
+        ));
+        let (t, theta) = (turbidity, FRAC_PI_2 - sun_elevation.clamp(0., FRAC_PI_2));
+
+        let perez = [
+            Perez::new(t, [[0.1787, -1.4630], [-0.3554, 0.4275], [-0.0227, 5.3251], [0.1206, -2.5771], [-0.0670, 0.3703]]),
+            Perez::new(t, [[-0.0193, -0.2592], [-0.0665, 0.0008], [-0.0004, 0.2125], [-0.0641, -0.8989], [-0.0033, 0.0452]]),
+            Perez::new(t, [[-0.0167, -0.2608], [-0.0950, 0.0092], [-0.0079, 0.2102], [-0.0441, -1.6537], [-0.0109, 0.0529]]),
+        ];
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta);
+        let cubic = |c: [f64; 4]| c[0] * theta.powi(3) + c[1] * theta.powi(2) + c[2] * theta + c[3];
+        let zenith = [
+            (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192,
+            t * t * cubic([0.00166, -0.00375, 0.00209, 0.])
+                + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+                + cubic([0.11693, -0.21196, 0.06052, 0.25886]),
+            t * t * cubic([0.00275, -0.00610, 0.00317, 0.])
+                + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+                + cubic([0.15346, -0.26756, 0.06670, 0.26688]),
+        ];
+
+        let mut sky = PhysicalSky {
+            sun_elevation,
+            sun_azimuth,
+            turbidity,
+            ground_albedo,
+            intensity,
+            sun,
+            perez,
+            zenith,
+            ground: RgbIntensity::zeros(),
+            sun_light: false,
+        };
+        let irradiance = sky.sky_irradiance() + sky.sun_color() * sky.sun_illuminance() * sun_elevation.sin().max(0.) as f32;
+        sky.ground = ground_albedo.component_mul(&irradiance) / std::f32::consts::PI;
+        sky
+    }
+
+    /// Direction of travel, color and irradiance of the sunlight reaching the ground, reddened
+    /// by the air it went through. Dark once the sun has set.
+    pub fn sun(&self) -> DirectionalLight {
+        let mut light = DirectionalLight::new(-self.sun.into_inner(), self.sun_illuminance(), self.sun_color());
+        light.angular_diameter = SUN_ANGULAR_DIAMETER;
+        light
+    }
+
+    pub fn with_sun_light(mut self, sun_light: bool) -> Self {
+        self.sun_light = sun_light;
+        self
+    }
+
+    /// `sun` when the scene is to take it along with the sky.
+    pub fn sun_light(&self) -> Option<DirectionalLight> {
+        self.sun_light.then(|| self.sun())
+    }
+
+    fn sun_illuminance(&self) -> f32 {
+        if self.sun_elevation < 0. { 0. } else { SUN_ILLUMINANCE * self.intensity }
+    }
+
+    fn sun_color(&self) -> RgbIntensity {
+        let zenith_angle = (FRAC_PI_2 - self.sun_elevation.clamp(0., FRAC_PI_2)).to_degrees();
+        let mass = 1. / (zenith_angle.to_radians().cos() + 0.15 * (93.885 - zenith_angle).powf(-1.253));
+        RgbIntensity::from([0.61, 0.55, 0.465].map(|wavelength| transmittance(wavelength, mass, self.turbidity) as f32))
+    }
+
+    /// Sky light falling on the ground, summed over a grid on the upper hemisphere.
+    fn sky_irradiance(&self) -> RgbIntensity {
+        const STEPS: usize = 32;
+        let (d_theta, d_phi) = (FRAC_PI_2 / STEPS as f64, TAU / (2 * STEPS) as f64);
+        let mut irradiance = RgbIntensity::zeros();
+        for i in 0..STEPS {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..2 * STEPS {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let direction = Vector::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos(), 0.);
+                irradiance += self.sky(&direction) * (theta.cos() * theta.sin() * d_theta * d_phi) as f32;
+            }
+        }
+        irradiance
+    }
+
+    /// Sky radiance along `direction` above the horizon, in linear sRGB.
+    fn sky(&self, direction: &Vector) -> RgbIntensity {
+        let cos_theta = direction.z.max(0.);
+        let gamma = direction.normalize().dot(&self.sun).clamp(-1., 1.).acos();
+        let sun_theta = FRAC_PI_2 - self.sun_elevation.clamp(0., FRAC_PI_2);
+        let [luminance, x, y] = std::array::from_fn(|i| {
+            self.zenith[i] * self.perez[i].value(cos_theta, gamma) / self.perez[i].value(1., sun_theta)
+        });
+
+        let (big_x, big_z) = (x / y * luminance, (1. - x - y) / y * luminance);
+        let rgb = RgbIntensity::new(
+            (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z) as f32,
+            (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z) as f32,
+            (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z) as f32,
+        );
+        rgb.map(|c| c.max(0.)) * self.intensity
+    }
+
+    pub fn evaluate(&self, direction: &Vector) -> RgbIntensity {
+        if direction.z < 0. { self.ground } else { self.sky(direction) }
+    }
+}
+
+impl From<PhysicalSkyConfig> for PhysicalSky {
+    fn from(config: PhysicalSkyConfig) -> Self {
+        PhysicalSky::new(config.sun_elevation, config.sun_azimuth, config.turbidity, config.ground_albedo, config.intensity)
+            .with_sun_light(config.sun)
+    }
+}
+
+impl From<PhysicalSky> for PhysicalSkyConfig {
+    fn from(sky: PhysicalSky) -> Self {
+        PhysicalSkyConfig {
+            sun_elevation: sky.sun_elevation,
+            sun_azimuth: sky.sun_azimuth,
+            turbidity: sky.turbidity,
+            ground_albedo: sky.ground_albedo,
+            intensity: sky.intensity,
+            sun: sky.sun_light,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PhysicalSky;
+    use crate::renderer::objects::ray::Vector;
+
+    #[test]
+    fn test_blue_sky_brightest_around_the_sun() {
+        let sky: PhysicalSky = serde_yaml::from_str("{sun_elevation: 0.5, sun_azimuth: 0}").unwrap();
+        let zenith = sky.evaluate(&Vector::new(0., 0., 1., 0.));
+        assert!(zenith.z > zenith.x);
+
+        let near_sun = sky.evaluate(&Vector::new(0.5f64.cos(), 0.1, 0.5f64.sin(), 0.));
+        let away = sky.evaluate(&Vector::new(-(0.5f64.cos()), 0., 0.5f64.sin(), 0.));
+        assert!(near_sun.y > 2. * away.y);
+
+        let ground = sky.evaluate(&Vector::new(0., 0., -1., 0.));
+        assert!(ground.min() > 0. && ground.max() < zenith.max() * 10.);
+    }
+
+    #[test]
+    fn test_low_sun_is_redder() {
+        let high = PhysicalSky::new(1.2, 0., 3., [0.2; 3].into(), 0.1).sun();
+        let low = PhysicalSky::new(0.05, 0., 3., [0.2; 3].into(), 0.1).sun();
+
+        assert!(low.color.x / low.color.z > high.color.x / high.color.z);
+        assert!(low.color.y < high.color.y);
+        assert!(high.direction.z < 0.);
+        assert_eq!(PhysicalSky::new(-0.1, 0., 3., [0.2; 3].into(), 0.1).sun().intensity, 0.);
+    }
+}
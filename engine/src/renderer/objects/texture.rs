@@ -0,0 +1,332 @@
+pub mod procedural;
+
+use std::error::Error;
+use std::sync::Arc;
+
+use image::Rgb32FImage;
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::hit::Hit;
+use crate::renderer::objects::ray::{RgbIntensity, Unit, Uv, Vector};
+use crate::renderer::objects::texture::procedural::{Checker, Grid, Marble, Noise, Wood};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    MirroredRepeat,
+    Clamp,
+}
+
+impl WrapMode {
+    /// Maps an arbitrary texture coordinate into `[0, 1]`.
+    fn apply(&self, t: f64) -> f64 {
+        match self {
+            WrapMode::Repeat => t - t.floor(),
+            WrapMode::MirroredRepeat => {
+                let t = t.rem_euclid(2.);
+                if t > 1. { 2. - t } else { t }
+            }
+            WrapMode::Clamp => t.clamp(0., 1.),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImageTextureConfig {
+    file: String,
+    #[serde(default)]
+    wrap: WrapMode,
+    #[serde(default)]
+    filter: Filter,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "ImageTextureConfig", into = "ImageTextureConfig")]
+pub struct ImageTexture {
+    file: String,
+    wrap: WrapMode,
+    filter: Filter,
+    image: Arc<Rgb32FImage>,
+}
+
+impl ImageTexture {
+    pub fn new(file: String, wrap: WrapMode, filter: Filter) -> Result<Self, Box<dyn Error>> {
+        let image = image::open(&file)?.into_rgb32f();
+        Ok(ImageTexture { file, wrap, filter, image: image.into() })
+    }
+
+    pub fn from_image(image: Rgb32FImage, wrap: WrapMode, filter: Filter) -> Self {
+        ImageTexture { file: String::new(), wrap, filter, image: image.into() }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> RgbIntensity {
+        let (width, height) = self.image.dimensions();
+        let wrap_index = |i: i64, size: u32| -> u32 {
+            let size = size as i64;
+            let wrapped = match self.wrap {
+                WrapMode::Repeat => i.rem_euclid(size),
+                WrapMode::MirroredRepeat => {
+                    let i = i.rem_euclid(2 * size);
+                    if i >= size { 2 * size - 1 - i } else { i }
+                }
+                WrapMode::Clamp => i.clamp(0, size - 1),
+            };
+            wrapped as u32
+        };
+        self.image.get_pixel(wrap_index(x, width), wrap_index(y, height)).0.into()
+    }
+
+    pub fn sample(&self, uv: &Uv) -> RgbIntensity {
+        let (width, height) = self.image.dimensions();
+        let u = self.wrap.apply(uv.x);
+        let v = self.wrap.apply(uv.y);
+
+        // image rows go top to bottom, v goes bottom to top
+        let x = u * width as f64;
+        let y = (1. - v) * height as f64;
+
+        match self.filter {
+            Filter::Nearest => self.texel(x.floor() as i64, y.floor() as i64),
+            Filter::Bilinear => {
+                let x = x - 0.5;
+                let y = y - 0.5;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = ((x - x0) as f32, (y - y0) as f32);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+
+                let top = self.texel(x0, y0) * (1. - fx) + self.texel(x0 + 1, y0) * fx;
+                let bottom = self.texel(x0, y0 + 1) * (1. - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+                top * (1. - fy) + bottom * fy
+            }
+        }
+    }
+}
+
+impl TryFrom<ImageTextureConfig> for ImageTexture {
+    type Error = String;
+
+    fn try_from(config: ImageTextureConfig) -> Result<Self, Self::Error> {
+        ImageTexture::new(config.file.clone(), config.wrap, config.filter)
+            .map_err(|e| format!("failed to load texture {}: {}", config.file, e))
+    }
+}
+
+impl From<ImageTexture> for ImageTextureConfig {
+    fn from(texture: ImageTexture) -> Self {
+        ImageTextureConfig { file: texture.file, wrap: texture.wrap, filter: texture.filter }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Texture {
+    Image(ImageTexture),
+    Checker(Checker),
+    Grid(Grid),
+    Noise(Noise),
+    Marble(Marble),
+    Wood(Wood),
+}
+
+impl Texture {
+    /// Color at a surface point; `normal` is the geometric one.
+    pub fn evaluate(&self, uv: &Uv, pos: &Vector, normal: &Vector) -> RgbIntensity {
+        match self {
+            Texture::Image(image) => image.sample(uv),
+            Texture::Checker(checker) => checker.evaluate(uv, pos, normal),
+            Texture::Grid(grid) => grid.evaluate(uv, pos, normal),
+            Texture::Noise(noise) => noise.evaluate(uv, pos),
+            Texture::Marble(marble) => marble.evaluate(uv, pos),
+            Texture::Wood(wood) => wood.evaluate(uv, pos),
+        }
+    }
+}
+
+fn default_strength() -> f64 {
+    1.
+}
+
+/// Tangent-space normal map, stored as `(n + 1) / 2` per channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NormalMap {
+    pub texture: Texture,
+    #[serde(default = "default_strength")]
+    pub strength: f64,
+}
+
+impl NormalMap {
+    pub fn perturb(&self, hit: &Hit) -> Unit {
+        let texel = self.texture.evaluate(&hit.uv, &hit.pos, &hit.geometric_normal).map(|c| 2. * c as f64 - 1.);
+        Unit::try_new(
+            hit.tangent.scale(texel.x * self.strength)
+                + hit.bitangent().scale(texel.y * self.strength)
+                + hit.normal.scale(texel.z),
+            f64::EPSILON,
+        )
+        .unwrap_or(hit.normal)
+    }
+}
+
+/// Scalar height map, read from the first channel. `delta` is the finite difference step,
+/// applied both in UV and along the surface.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BumpMap {
+    pub texture: Texture,
+    #[serde(default = "default_strength")]
+    pub strength: f64,
+    #[serde(default = "BumpMap::default_delta")]
+    pub delta: f64,
+}
+
+impl BumpMap {
+    fn default_delta() -> f64 {
+        1e-3
+    }
+
+    fn height(&self, uv: Uv, pos: Vector, normal: &Vector) -> f64 {
+        self.texture.evaluate(&uv, &pos, normal).x as f64
+    }
+
+    pub fn perturb(&self, hit: &Hit) -> Unit {
+        let bitangent = hit.bitangent();
+        let height = self.height(hit.uv, hit.pos, &hit.geometric_normal);
+        let du = (self.height(hit.uv + Uv::new(self.delta, 0.), hit.pos + hit.tangent.scale(self.delta), &hit.geometric_normal)
+            - height)
+            / self.delta;
+        let dv = (self.height(hit.uv + Uv::new(0., self.delta), hit.pos + bitangent.scale(self.delta), &hit.geometric_normal)
+            - height)
+            / self.delta;
+
+        Unit::try_new(
+            hit.normal.into_inner()
+                - (hit.tangent.scale(du) + bitangent.scale(dv)).scale(self.strength),
+            f64::EPSILON,
+        )
+        .unwrap_or(hit.normal)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+/// How a mesh without its own texture coordinates gets its UVs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UvProjection {
+    /// Projects along the dominant axis of the face normal.
+    #[default]
+    Box,
+    /// Projects every face along one fixed axis.
+    Planar(Axis),
+}
+
+impl UvProjection {
+    fn axis(&self, normal: &Vector) -> Axis {
+        match self {
+            UvProjection::Planar(axis) => *axis,
+            UvProjection::Box => {
+                let n = normal.abs();
+                if n.x >= n.y && n.x >= n.z {
+                    Axis::X
+                } else if n.y >= n.z {
+                    Axis::Y
+                } else {
+                    Axis::Z
+                }
+            }
+        }
+    }
+
+    /// `local` is the point relative to the mesh origin, divided by the mesh size.
+    pub fn project(&self, local: &Vector, normal: &Vector) -> Uv {
+        match self.axis(normal) {
+            Axis::X => Uv::new(local.y, local.z),
+            Axis::Y => Uv::new(local.x, local.z),
+            Axis::Z => Uv::new(local.x, local.y),
+        }
+    }
+
+    /// The world direction of increasing `u`, projected onto the face.
+    pub fn tangent(&self, normal: &Unit) -> Unit {
+        let u_axis = match self.axis(normal) {
+            Axis::X => Vector::y(),
+            Axis::Y | Axis::Z => Vector::x(),
+        };
+        Hit::orthogonalized(&u_axis, normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use image::{Rgb, Rgb32FImage};
+
+    use super::{Filter, ImageTexture, NormalMap, Texture, WrapMode};
+    use crate::renderer::objects::hit::Hit;
+    use crate::renderer::objects::material::Material;
+    use crate::renderer::objects::ray::{RgbIntensity, Unit, Uv, Vector};
+
+    fn two_by_one(wrap: WrapMode, filter: Filter) -> ImageTexture {
+        let mut image = Rgb32FImage::new(2, 1);
+        image.put_pixel(0, 0, Rgb([0., 0., 0.]));
+        image.put_pixel(1, 0, Rgb([1., 1., 1.]));
+        ImageTexture::from_image(image, wrap, filter)
+    }
+
+    #[test]
+    fn test_bilinear_between_texel_centers() {
+        let texture = two_by_one(WrapMode::Clamp, Filter::Bilinear);
+
+        assert_relative_eq!(texture.sample(&Uv::new(0.25, 0.5)), RgbIntensity::zeros());
+        assert_relative_eq!(texture.sample(&Uv::new(0.5, 0.5)), RgbIntensity::from([0.5; 3]));
+        assert_relative_eq!(texture.sample(&Uv::new(0.75, 0.5)), RgbIntensity::from([1.; 3]));
+    }
+
+    #[test]
+    fn test_wrap_modes() {
+        let repeat = two_by_one(WrapMode::Repeat, Filter::Nearest);
+        let mirrored = two_by_one(WrapMode::MirroredRepeat, Filter::Nearest);
+        let clamp = two_by_one(WrapMode::Clamp, Filter::Nearest);
+
+        assert_relative_eq!(repeat.sample(&Uv::new(1.25, 0.5)), RgbIntensity::zeros());
+        assert_relative_eq!(mirrored.sample(&Uv::new(1.25, 0.5)), RgbIntensity::from([1.; 3]));
+        assert_relative_eq!(clamp.sample(&Uv::new(-3., 0.5)), RgbIntensity::zeros());
+    }
+
+    #[test]
+    fn test_normal_map_tangent_space() {
+        let mut image = Rgb32FImage::new(1, 1);
+        let material = Material::default();
+        let normal = Unit::new_normalize(Vector::new(0., 0., 1., 0.));
+        let hit = Hit::new(1., Vector::zeros(), &material, normal, Uv::zeros(), Hit::default_tangent(&normal));
+
+        image.put_pixel(0, 0, Rgb([0.5, 0.5, 1.]));
+        let flat = NormalMap {
+            texture: Texture::Image(ImageTexture::from_image(image.clone(), WrapMode::Repeat, Filter::Nearest)),
+            strength: 1.,
+        };
+        assert_relative_eq!(flat.perturb(&hit), normal);
+
+        image.put_pixel(0, 0, Rgb([1., 0.5, 0.5]));
+        let tilted = NormalMap {
+            texture: Texture::Image(ImageTexture::from_image(image, WrapMode::Repeat, Filter::Nearest)),
+            strength: 1.,
+        };
+        assert_relative_eq!(tilted.perturb(&hit), hit.tangent);
+    }
+}
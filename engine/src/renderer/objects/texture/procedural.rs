@@ -0,0 +1,370 @@
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::objects::ray::{RgbIntensity, Uv, Vector, Vector3};
+
+/// Ken Perlin's reference permutation, repeated so lookups never wrap.
+const PERMUTATION: [u8; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69,
+    142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219,
+    203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175,
+    74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230,
+    220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76,
+    132, 187, 208, 89, 18, 169, 200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173,
+    186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118, 126, 255, 82, 85, 212, 207, 206,
+    59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163,
+    70, 221, 153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232,
+    178, 185, 112, 104, 218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162,
+    241, 81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204,
+    176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141,
+    128, 195, 78, 66, 215, 61, 156, 180,
+];
+
+fn hash(i: i64) -> usize {
+    PERMUTATION[i.rem_euclid(256) as usize] as usize
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+fn grad(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Improved Perlin noise, roughly in `[-1, 1]`.
+pub fn perlin(p: &Vector3) -> f64 {
+    let (xi, yi, zi) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+    let (x, y, z) = (p.x - p.x.floor(), p.y - p.y.floor(), p.z - p.z.floor());
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let a = hash(xi) + yi.rem_euclid(256) as usize;
+    let aa = hash(a as i64) + zi.rem_euclid(256) as usize;
+    let ab = hash(a as i64 + 1) + zi.rem_euclid(256) as usize;
+    let b = hash(xi + 1) + yi.rem_euclid(256) as usize;
+    let ba = hash(b as i64) + zi.rem_euclid(256) as usize;
+    let bb = hash(b as i64 + 1) + zi.rem_euclid(256) as usize;
+
+    lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, grad(hash(aa as i64), x, y, z), grad(hash(ba as i64), x - 1., y, z)),
+            lerp(u, grad(hash(ab as i64), x, y - 1., z), grad(hash(bb as i64), x - 1., y - 1., z)),
+        ),
+        lerp(
+            v,
+            lerp(
+                u,
+                grad(hash(aa as i64 + 1), x, y, z - 1.),
+                grad(hash(ba as i64 + 1), x - 1., y, z - 1.),
+            ),
+            lerp(
+                u,
+                grad(hash(ab as i64 + 1), x, y - 1., z - 1.),
+                grad(hash(bb as i64 + 1), x - 1., y - 1., z - 1.),
+            ),
+        ),
+    )
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Octaves {
+    pub octaves: usize,
+    pub lacunarity: f64,
+    pub gain: f64,
+}
+
+impl Default for Octaves {
+    fn default() -> Self {
+        Octaves { octaves: 4, lacunarity: 2., gain: 0.5 }
+    }
+}
+
+impl Octaves {
+    /// Fractal sum of Perlin noise, normalized to roughly `[-1, 1]`.
+    pub fn fbm(&self, p: &Vector3) -> f64 {
+        self.sum(p, perlin)
+    }
+
+    /// Fractal sum of `|noise|`, in `[0, 1]`.
+    pub fn turbulence(&self, p: &Vector3) -> f64 {
+        self.sum(p, |p| perlin(p).abs())
+    }
+
+    fn sum(&self, p: &Vector3, noise: impl Fn(&Vector3) -> f64) -> f64 {
+        let mut total = 0.;
+        let mut norm = 0.;
+        let mut amplitude = 1.;
+        let mut frequency = 1.;
+        for _ in 0..self.octaves.max(1) {
+            total += amplitude * noise(&p.scale(frequency));
+            norm += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+        total / norm
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Space {
+    /// Evaluated at the hit position.
+    #[default]
+    World,
+    /// Evaluated at the hit UVs, with `z = 0`.
+    Uv,
+}
+
+/// Where a procedural pattern is evaluated. `scale` is the size of one pattern cell.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Placement {
+    pub space: Space,
+    pub scale: Vector3,
+    pub offset: Vector3,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Placement { space: Space::World, scale: Vector3::from([1.; 3]), offset: Vector3::zeros() }
+    }
+}
+
+impl Placement {
+    pub fn point(&self, uv: &Uv, pos: &Vector) -> Vector3 {
+        let p = match self.space {
+            Space::World => pos.xyz(),
+            Space::Uv => Vector3::new(uv.x, uv.y, 0.),
+        };
+        (p + self.offset).component_div(&self.scale)
+    }
+
+    /// Axis a pattern on a surface with `normal` leaves out: in world space the one closest
+    /// to the normal, as the surface runs along its planes rather than across them.
+    fn flat_axis(&self, normal: &Vector) -> usize {
+        match self.space {
+            Space::World => normal.xyz().abs().imax(),
+            Space::Uv => 2,
+        }
+    }
+}
+
+fn mix(a: &RgbIntensity, b: &RgbIntensity, t: f64) -> RgbIntensity {
+    a.lerp(b, t.clamp(0., 1.) as f32)
+}
+
+/// Cells alternating on every axis but the one `Placement::flat_axis` leaves out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checker {
+    pub even: RgbIntensity,
+    pub odd: RgbIntensity,
+    #[serde(default, flatten)]
+    pub placement: Placement,
+}
+
+impl Checker {
+    pub fn evaluate(&self, uv: &Uv, pos: &Vector, normal: &Vector) -> RgbIntensity {
+        let p = self.placement.point(uv, pos);
+        let flat = self.placement.flat_axis(normal);
+        let parity = (0..3).filter(|&i| i != flat).map(|i| p[i].floor()).sum::<f64>() as i64;
+        if parity.rem_euclid(2) == 0 { self.even } else { self.odd }
+    }
+}
+
+/// Lines on the integer planes of every axis but the one `Placement::flat_axis` leaves out;
+/// `line_width` is a fraction of a cell.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grid {
+    pub line: RgbIntensity,
+    pub background: RgbIntensity,
+    #[serde(default = "Grid::default_line_width")]
+    pub line_width: f64,
+    #[serde(default, flatten)]
+    pub placement: Placement,
+}
+
+impl Grid {
+    fn default_line_width() -> f64 {
+        0.05
+    }
+
+    pub fn evaluate(&self, uv: &Uv, pos: &Vector, normal: &Vector) -> RgbIntensity {
+        let p = self.placement.point(uv, pos);
+        let flat = self.placement.flat_axis(normal);
+        let on_line = (0..3).filter(|&i| i != flat).any(|i| {
+            let distance = p[i] - p[i].round();
+            distance.abs() < self.line_width / 2.
+        });
+        if on_line { self.line } else { self.background }
+    }
+}
+
+/// Perlin noise or fBm between two colors, depending on the octave count.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Noise {
+    pub low: RgbIntensity,
+    pub high: RgbIntensity,
+    #[serde(default, flatten)]
+    pub octaves: Octaves,
+    #[serde(default, flatten)]
+    pub placement: Placement,
+}
+
+impl Noise {
+    pub fn evaluate(&self, uv: &Uv, pos: &Vector) -> RgbIntensity {
+        let p = self.placement.point(uv, pos);
+        mix(&self.low, &self.high, 0.5 + 0.5 * self.octaves.fbm(&p))
+    }
+}
+
+/// Sine stripes along x, distorted by turbulence.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Marble {
+    pub base: RgbIntensity,
+    pub vein: RgbIntensity,
+    #[serde(default = "Marble::default_frequency")]
+    pub frequency: f64,
+    #[serde(default = "Marble::default_turbulence")]
+    pub turbulence: f64,
+    #[serde(default, flatten)]
+    pub octaves: Octaves,
+    #[serde(default, flatten)]
+    pub placement: Placement,
+}
+
+impl Default for Marble {
+    fn default() -> Self {
+        Marble {
+            base: RgbIntensity::from([0.85; 3]),
+            vein: RgbIntensity::from([0.35; 3]),
+            frequency: Self::default_frequency(),
+            turbulence: Self::default_turbulence(),
+            octaves: Octaves::default(),
+            placement: Placement::default(),
+        }
+    }
+}
+
+impl Marble {
+    fn default_frequency() -> f64 {
+        3.
+    }
+
+    fn default_turbulence() -> f64 {
+        5.
+    }
+
+    pub fn evaluate(&self, uv: &Uv, pos: &Vector) -> RgbIntensity {
+        let p = self.placement.point(uv, pos);
+        let phase = self.frequency * p.x + self.turbulence * self.octaves.turbulence(&p);
+        // sharpen the veins so most of the surface stays base colored
+        mix(&self.base, &self.vein, (1. - (0.5 + 0.5 * phase.sin())).powi(3))
+    }
+}
+
+/// Concentric rings around the z axis, wobbled by noise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Wood {
+    pub light: RgbIntensity,
+    pub dark: RgbIntensity,
+    #[serde(default = "Wood::default_rings")]
+    pub rings: f64,
+    #[serde(default = "Wood::default_turbulence")]
+    pub turbulence: f64,
+    #[serde(default, flatten)]
+    pub octaves: Octaves,
+    #[serde(default, flatten)]
+    pub placement: Placement,
+}
+
+impl Wood {
+    fn default_rings() -> f64 {
+        8.
+    }
+
+    fn default_turbulence() -> f64 {
+        0.1
+    }
+
+    pub fn evaluate(&self, uv: &Uv, pos: &Vector) -> RgbIntensity {
+        let p = self.placement.point(uv, pos);
+        let radius = p.x.hypot(p.y) + self.turbulence * self.octaves.fbm(&p);
+        let ring = (radius * self.rings).fract().abs();
+        mix(&self.light, &self.dark, ring * ring)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::{perlin, Checker, Grid, Placement, Space};
+    use crate::renderer::objects::ray::{RgbIntensity, Uv, Vector, Vector3};
+
+    #[test]
+    fn test_perlin_vanishes_on_lattice() {
+        for p in [Vector3::new(0., 0., 0.), Vector3::new(3., -7., 12.)] {
+            assert_relative_eq!(perlin(&p), 0.);
+        }
+        assert!(perlin(&Vector3::new(0.3, 0.6, 0.2)).abs() <= 1.);
+    }
+
+    #[test]
+    fn test_checker_placement() {
+        let checker = Checker {
+            even: RgbIntensity::from([1.; 3]),
+            odd: RgbIntensity::zeros(),
+            placement: Placement { space: Space::Uv, scale: Vector3::from([0.5; 3]), ..Placement::default() },
+        };
+        let (pos, up) = (Vector::zeros(), Vector::z());
+
+        assert_eq!(checker.evaluate(&Uv::new(0.25, 0.25), &pos, &up), checker.even);
+        assert_eq!(checker.evaluate(&Uv::new(0.75, 0.25), &pos, &up), checker.odd);
+        assert_eq!(checker.evaluate(&Uv::new(0.75, 0.75), &pos, &up), checker.even);
+    }
+
+    #[test]
+    fn test_world_checker_on_floor() {
+        let checker = Checker { even: RgbIntensity::from([1.; 3]), odd: RgbIntensity::zeros(), placement: Placement::default() };
+        let up = Vector::z();
+
+        // Either side of z = 0 by float noise, the same cell.
+        for z in [1e-12, -1e-12] {
+            assert_eq!(checker.evaluate(&Uv::zeros(), &Vector::new(0.5, 0.5, z, 0.), &up), checker.even);
+            assert_eq!(checker.evaluate(&Uv::zeros(), &Vector::new(1.5, 0.5, z, 0.), &up), checker.odd);
+        }
+        assert_eq!(checker.evaluate(&Uv::zeros(), &Vector::new(0.5, -0.5, 0., 0.), &up), checker.odd);
+    }
+
+    #[test]
+    fn test_world_grid_on_floor() {
+        let grid = Grid {
+            line: RgbIntensity::from([1.; 3]),
+            background: RgbIntensity::zeros(),
+            line_width: 0.1,
+            placement: Placement::default(),
+        };
+        let (uv, up) = (Uv::zeros(), Vector::z());
+
+        assert_eq!(grid.evaluate(&uv, &Vector::new(0.5, 0.5, 0., 0.), &up), grid.background);
+        assert_eq!(grid.evaluate(&uv, &Vector::new(2., 0.5, 0., 0.), &up), grid.line);
+        assert_eq!(grid.evaluate(&uv, &Vector::new(0.5, -3.01, 0., 0.), &up), grid.line);
+        // A wall along y = 0 shows lines along x and z instead.
+        assert_eq!(grid.evaluate(&uv, &Vector::new(0.5, 0., 0.5, 0.), &Vector::y()), grid.background);
+    }
+}
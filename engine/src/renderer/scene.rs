@@ -1,41 +1,128 @@
 use std::error::Error;
 use serde::{Deserialize, Serialize};
+use crate::renderer::objects::dielectric::DielectricStack;
 use crate::renderer::objects::hit::Hit;
-use crate::renderer::objects::model::{Model};
+use crate::renderer::objects::medium::Medium;
+use crate::renderer::objects::model::{Model, MIN_FACTOR};
 use crate::renderer::objects::ray::Ray;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Scene<M: Model> {
-    pub objects: Vec<M>
+    pub objects: Vec<M>,
+    /// Fills the space outside of the objects.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub medium: Option<Medium>,
 }
 
 impl<M: Model> Scene<M> {
     pub fn new(objects: Vec<M>) -> Self {
-        Scene { objects }
+        Scene { objects, medium: None }
     }
 
-    pub fn intersect(&self, ray: &Ray) -> Option<Hit> {
+    pub fn with_medium(mut self, medium: Medium) -> Self {
+        self.medium = Some(medium);
+        self
+    }
+
+    /// Medium a ray travels through before reaching `hit`: the inside of the object when the
+    /// ray leaves it, the scene medium otherwise.
+    pub fn medium_along<'a>(&'a self, hit: &Option<Hit<'a>>) -> Option<&'a Medium> {
+        match hit {
+            Some(hit) if !hit.front_face => hit.material.medium.as_ref(),
+            _ => self.medium.as_ref(),
+        }
+    }
+
+    /// Follows `ray`, just leaving `hit`, along its curved path through the gradient-index
+    /// medium behind the surface, if it goes into one. Returns the last straight step, which
+    /// reaches the boundary of the medium. Only the object of `hit` bounds the medium, so that
+    /// each step tests one object; others inside it are not seen along the curve.
+    pub fn bend(&self, ray: Ray, hit: &Hit) -> Ray {
+        match &hit.material.gradient_index {
+            Some(field) if ray.direction.dot(&hit.geometric_normal) < 0. => {
+                field.trace(ray, |segment| self.intersect_object(segment, hit.object).map(|hit| hit.factor))
+            }
+            _ => ray,
+        }
+    }
+
+    /// Closest hit along `ray`, going through the parts of surfaces that are cut out.
+    pub fn intersect(&self, ray: &Ray) -> Option<Hit<'_>> {
+        self.pass_through(ray, |hit| hit.is_cut_out(&ray.direction))
+    }
+
+    /// Closest hit along `ray` with the object at `index` alone, for rays that stay inside it.
+    pub fn intersect_object(&self, ray: &Ray, index: usize) -> Option<Hit<'_>> {
+        self.objects[index]
+            .hit(ray)
+            .filter(|hit| MIN_FACTOR < hit.factor)
+            .map(|hit| Hit { object: index, ..hit }.facing(&ray.direction).perturbed())
+    }
+
+    fn closest(&self, ray: &Ray) -> Option<Hit<'_>> {
         let mut closest_t = f64::INFINITY;
         let mut closest: Option<Hit> = None;
 
-        self.objects.iter().for_each(|object| {
+        self.objects.iter().enumerate().for_each(|(i, object)| {
             match object.hit(ray) {
-                None => {}
-                Some(hit) => {
-                    if 0.0000001 < hit.factor && hit.factor < closest_t {
-                        closest_t = hit.factor;
-                        closest = Some(hit);
-                    }
+                Some(hit) if MIN_FACTOR < hit.factor && hit.factor < closest_t => {
+                    closest_t = hit.factor;
+                    closest = Some(Hit { object: i, ..hit });
                 }
+                _ => {}
             };
         });
-        closest
+        closest.map(|hit| hit.facing(&ray.direction).perturbed())
+    }
+}
+
+impl<M: Model> Scene<M> {
+    const PASS_THROUGH_STEP: f64 = 1e-6;
+
+    /// Like `intersect`, but also passes through false interfaces between nested dielectrics,
+    /// updating `stack` on the way.
+    pub fn intersect_nested<'a>(&'a self, ray: &Ray, stack: &mut DielectricStack<'a>) -> Option<Hit<'a>> {
+        self.intersect_nested_with(ray, stack, |hit| hit.is_cut_out(&ray.direction))
+    }
+
+    /// Like `intersect_nested`, with `cut_out` deciding where surfaces are absent.
+    pub fn intersect_nested_with<'a>(
+        &'a self,
+        ray: &Ray,
+        stack: &mut DielectricStack<'a>,
+        mut cut_out: impl FnMut(&Hit<'a>) -> bool,
+    ) -> Option<Hit<'a>> {
+        self.pass_through(ray, |hit| {
+            if cut_out(hit) {
+                *stack = stack.cut_through(hit);
+            } else if !stack.is_boundary(hit) {
+                *stack = stack.crossed(hit);
+            } else {
+                return false;
+            }
+            true
+        })
+    }
+
+    /// First hit along `ray` that `skip` does not pass through, with the factor measured from
+    /// the ray origin.
+    fn pass_through<'a>(&'a self, ray: &Ray, mut skip: impl FnMut(&Hit<'a>) -> bool) -> Option<Hit<'a>> {
+        let mut current = ray.clone();
+        let mut travelled = 0.;
+        loop {
+            let hit = self.closest(&current)?;
+            if !skip(&hit) {
+                return Some(Hit { factor: hit.factor + travelled, ..hit });
+            }
+            travelled += hit.factor + Self::PASS_THROUGH_STEP;
+            current.origin = hit.pos + current.direction.scale(Self::PASS_THROUGH_STEP);
+        }
     }
 }
 
 impl<M: Model + for<'de> Deserialize<'de>> Scene<M> {
-    pub fn load_scene(data: &String) -> Result<Self, Box<dyn Error>> {
-        serde_yaml::from_str::<Scene<M>>(data.as_str()).map_err(|e| e.into())
+    pub fn load_scene(data: &str) -> Result<Self, Box<dyn Error>> {
+        serde_yaml::from_str::<Scene<M>>(data).map_err(|e| e.into())
     }
 }
 
@@ -44,3 +131,33 @@ impl<M: Model + Serialize> Scene<M> {
         serde_yaml::to_string(&self).map_err(|e| e.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::Scene;
+    use crate::renderer::objects::material::MaterialBuilder;
+    use crate::renderer::objects::material::gradient_index::IorField;
+    use crate::renderer::objects::model::sphere::SphereModel;
+    use crate::renderer::objects::ray::{Ray, Unit, Vector, Vector3};
+
+    #[test]
+    fn test_bend_stops_at_its_own_boundary() {
+        let field = IorField::Linear { ior: 1.3, gradient: Vector3::new(0., 0., 0.05), origin: Vector3::zeros() };
+        let lens = MaterialBuilder::default().transmission(true).gradient_index(Some(field)).build().unwrap();
+        let scene = Scene::new(vec![
+            SphereModel::new(Vector::zeros(), 2., lens),
+            SphereModel::new(Vector::new(0.5, 0., 0., 0.), 0.1, MaterialBuilder::default().build().unwrap()),
+        ]);
+        let entering = Ray::new(Vector::new(-3., 0., 0., 0.), Unit::new_normalize(Vector::new(1., 0., 0., 0.)), 1.);
+        let hit = scene.intersect(&entering).unwrap();
+        assert_eq!(hit.object, 0);
+
+        let inside = Ray::new(hit.pos + entering.direction.scale(1e-6), entering.direction, 1.3);
+        let last = scene.bend(inside, &hit);
+        let exit = scene.intersect_object(&last, 0).unwrap();
+        assert_relative_eq!(exit.pos.magnitude(), 2., epsilon = 1e-6);
+        assert!(exit.pos.z > 0.);
+    }
+}
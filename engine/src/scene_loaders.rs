@@ -1,28 +1,116 @@
 #![allow(dead_code)]
 
+use std::collections::BTreeMap;
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
-use crate::renderer::implementations::global_illumination::PointLight;
-use crate::renderer::objects::camera::Camera;
+use serde_yaml::Value;
 use crate::renderer::objects::camera::perspective::PerspectiveCamera;
+use crate::renderer::objects::caustics::Caustics;
+use crate::renderer::objects::environment::Environment;
+use crate::renderer::objects::light::Light;
+use crate::renderer::objects::light::sampler::LightSampling;
+use crate::renderer::objects::material::library::MaterialLibrary;
+use crate::renderer::objects::material::Material;
 use crate::renderer::objects::model::triangle::TriangleModel;
 use crate::renderer::scene::Scene;
 
 #[derive(Clone, Debug, Serialize,Deserialize, Builder)]
 pub struct GlobalIlluminationCollection {
-    pub lights: Vec<PointLight>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub materials: BTreeMap<String, Material>,
+    pub lights: Vec<Light>,
+    #[builder(default)]
+    #[serde(default)]
+    pub environment: Environment,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caustics: Option<Caustics>,
+    /// Shades each point with a few of the lights rather than all of them.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light_sampling: Option<LightSampling>,
+    /// Where to save one image per light from the first camera, `{}` standing for the
+    /// index of the light.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light_images: Option<String>,
     pub cameras: Vec<PerspectiveCamera>,
     pub scene: Scene<TriangleModel>
 }
 
 impl GlobalIlluminationCollection {
-    pub fn load(data: &String) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut collection: Self = serde_yaml::from_str(data)?;
+    pub fn load(data: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut document: Value = serde_yaml::from_str(data)?;
+        Self::resolve_materials(&mut document)?;
+
+        let mut collection: Self = serde_yaml::from_value(document)?;
         collection.scene.objects = collection.scene.objects.iter().map(|obj| { obj.clone().load_file().unwrap() }).collect();
         Ok(collection)
     }
 
+    /// `lights` with the sun of a sky that asks for it, which stays out of `lights` so that
+    /// saving does not write it twice.
+    pub fn scene_lights(&self) -> Vec<Light> {
+        self.lights.iter().cloned().chain(self.environment.background.sun().map(Light::from)).collect()
+    }
+
     pub fn save(&self) -> Result<String, Box<dyn std::error::Error>> {
         serde_yaml::to_string(&self).map_err(|e| e.into())
     }
-}
\ No newline at end of file
+
+    /// Expands `materials:` entries and named object materials into full materials.
+    fn resolve_materials(document: &mut Value) -> Result<(), Box<dyn std::error::Error>> {
+        let mut library = MaterialLibrary::builtin();
+
+        if let Some(Value::Mapping(materials)) = document.get_mut("materials") {
+            library.extend(materials)?;
+            for (name, material) in materials.iter_mut() {
+                *material = library.resolve(name)?;
+            }
+        }
+
+        if let Some(Value::Sequence(objects)) = document
+            .get_mut("scene")
+            .and_then(|scene| scene.get_mut("objects"))
+        {
+            for object in objects {
+                if let Some(material) = object.get_mut("material") {
+                    *material = library.resolve(material)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GlobalIlluminationCollection;
+    use crate::renderer::objects::light::Light;
+
+    #[test]
+    fn test_sky_brings_its_sun() {
+        let collection = GlobalIlluminationCollection::load(
+            "lights: []\n\
+             environment: {background: {type: sky, sun_elevation: 0.5, sun: true}}\n\
+             cameras: []\n\
+             scene: {objects: []}\n",
+        )
+        .unwrap();
+        let lights = collection.scene_lights();
+        let [Light::Directional(sun)] = lights.as_slice() else {
+            panic!("expected the sun alone, got {lights:?}");
+        };
+        assert!(sun.direction.z < 0. && sun.intensity > 0.);
+        let saved = GlobalIlluminationCollection::load(&collection.save().unwrap()).unwrap();
+        assert!(saved.lights.is_empty());
+        assert_eq!(saved.scene_lights().len(), 1);
+
+        let collection = GlobalIlluminationCollection::load(
+            "lights: []\nenvironment: {background: {type: sky, sun_elevation: 0.5}}\ncameras: []\nscene: {objects: []}\n",
+        )
+        .unwrap();
+        assert!(collection.scene_lights().is_empty());
+    }
+}
@@ -10,7 +10,12 @@ use crate::renderer::implementations::simple_illumination::SimpleIllumination;
 use crate::renderer::objects::material::MaterialBuilder;
 
 use common::Common;
-use crate::renderer::implementations::sampling::{Black, Sampling};
+use crate::renderer::implementations::sampling::Sampling;
+use crate::renderer::objects::environment::Environment;
+use crate::renderer::implementations::global_illumination::GlobalIllumination;
+use crate::renderer::objects::light::{Light, PointLight};
+use crate::renderer::objects::ray::{Ray, Unit};
+use crate::renderer::Renderer;
 
 #[test]
 fn test_simple_renderer_sphere_model() {
@@ -24,7 +29,7 @@ fn test_simple_renderer_sphere_model() {
         std::f64::consts::FRAC_PI_6
     );
 
-    let renderer = Sampling::new(Scene::new(Common::get_3_spheres()), Black{}, 3, rand_pcg::Pcg64Mcg::seed_from_u64(0), 50);
+    let renderer = Sampling::new(Scene::new(Common::get_3_spheres()), Environment::constant([0.2; 3].into()), 3, rand_pcg::Pcg64Mcg::seed_from_u64(0), 50);
 
     Common::generate_image("sphere_model.png", &cam, &renderer);
 }
@@ -50,7 +55,7 @@ fn test_simple_renderer_triangle_model() {
                 .roughness(Rgb::from_pixel([200, 200, 200]))
                 .k(4.).build().unwrap()
         ).load_file().unwrap().to_owned()
-    ]), Black{}, 3, rand_pcg::Pcg64Mcg::seed_from_u64(0), 50);
+    ]), Environment::constant([0.2; 3].into()), 3, rand_pcg::Pcg64Mcg::seed_from_u64(0), 50);
 
     Common::generate_image("triangle_model.png", &cam, &renderer);
 }
@@ -67,7 +72,39 @@ fn test_simple_renderer_cam_reposition() {
         std::f64::consts::FRAC_PI_6
     );
 
-    let renderer = SimpleIllumination::new(Scene::new(Common::get_3_spheres()));
+    let renderer = SimpleIllumination::new(Scene::new(Common::get_3_spheres()), Environment::constant([1.; 3].into()));
 
     Common::generate_image("cam_reposition.png", &cam, &renderer);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_open_surface_from_behind() {
+    Common::setup();
+    let dims = Common::DIMENSIONS;
+
+    // The wall faces -y; camera and light are on its back side.
+    let cam = PerspectiveCamera::new(
+        Vector::new(0., 20., 0., 0.),
+        Vector::new(0., 0., 0., 0.),
+        dims.clone(),
+        std::f64::consts::FRAC_PI_3,
+    );
+    let wall = || {
+        TriangleModel::new(
+            "../test_data/plane_wall.stl".into(),
+            MaterialBuilder::default().color([0.8; 3].into()).roughness([1.; 3].into()).build().unwrap(),
+        ).load_file().unwrap()
+    };
+    let lights: Vec<Light> = vec![PointLight::new(Vector::new(0., 12., 2., 0.), 100., [1.; 3].into()).into()];
+    // Off the diagonal between the two triangles.
+    let ray = Ray::new(Vector::new(1., 20., -2., 0.), Unit::new_normalize(Vector::new(0., -1., 0., 0.)), 1.);
+
+    let global_illumination = GlobalIllumination::new(Scene::new(vec![wall()]), lights.clone(), 2, Environment::default());
+    assert!(global_illumination.cast(&ray).x > 0.1);
+    Common::generate_image("open_surface_gi.png", &cam, &global_illumination);
+
+    let sampling = Sampling::new(Scene::new(vec![wall()]), Environment::default(), 2, rand_pcg::Pcg64Mcg::seed_from_u64(0), 4)
+        .with_lights(lights);
+    assert!(sampling.cast(&ray).x > 0.1 / std::f32::consts::PI);
+    Common::generate_image("open_surface_sampling.png", &cam, &sampling);
+}